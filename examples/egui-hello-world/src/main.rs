@@ -2,7 +2,7 @@
 //! Ported from <https://github.com/emilk/egui/tree/master/examples/hello_world>
 
 use eframe::egui;
-use retained::retained;
+use retained::{retained, WithState};
 
 fn main() -> Result<(), eframe::Error> {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
@@ -22,8 +22,26 @@ fn main() -> Result<(), eframe::Error> {
     )
 }
 
-#[retained(AppState)]
-fn retained_update(ctx: &egui::Context, _frame: &mut eframe::Frame) {
+struct App {
+    state: WithState<Update, AppState>,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self {
+            state: WithState::new(AppState::new()),
+        }
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.state.call((ctx,));
+    }
+}
+
+#[retained(AppState, marker = Update)]
+fn update(ctx: &egui::Context) {
     egui::CentralPanel::default().show(ctx, |ui| {
         ui.heading("My egui Application");
 
@@ -46,17 +64,3 @@ fn retained_update(ctx: &egui::Context, _frame: &mut eframe::Frame) {
         ui.image(egui::include_image!("../ferris.png"));
     });
 }
-
-struct App(AppState);
-
-impl Default for App {
-    fn default() -> Self {
-        Self(AppState::new())
-    }
-}
-
-impl eframe::App for App {
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        retained_update(ctx, frame, &mut self.0);
-    }
-}
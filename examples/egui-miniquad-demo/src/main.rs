@@ -18,11 +18,38 @@ impl Stage {
     }
 }
 
+#[retained]
 impl mq::EventHandler for Stage {
     fn update(&mut self) {}
 
+    #[retained(State(mq_ctx: Box<dyn mq::RenderingBackend>), from = self.state)]
     fn draw(&mut self) {
-        draw(&mut self.egui_mq, &mut self.state);
+        #[retained(default)]
+        let ref mut mq_ctx: Box<dyn mq::RenderingBackend> = mq_ctx;
+        let mq_ctx = &mut **mq_ctx;
+
+        mq_ctx.clear(Some((1., 1., 1., 1.)), None, None);
+        mq_ctx.begin_default_pass(mq::PassAction::clear_color(0.0, 0.0, 0.0, 1.0));
+        mq_ctx.end_render_pass();
+
+        // Run the UI code:
+        self.egui_mq.run(mq_ctx, |_mq_ctx, egui_ctx| {
+            #[retained(default)]
+            let ref mut demo_ui_state: DemoUi = DemoUi::new();
+            demo_ui(egui_ctx, demo_ui_state);
+
+            // See what is retained by the ui in debug builds
+            #[cfg(debug_assertions)]
+            egui::Window::new("Retained state").show(egui_ctx, |ui| {
+                ui.add(retained::Inspector::new(demo_ui_state).default_open(true));
+            });
+        });
+
+        // Draw things behind egui here
+        self.egui_mq.draw(&mut *mq_ctx);
+
+        // Draw things in front of egui here
+        mq_ctx.commit_frame();
     }
 
     fn mouse_motion_event(&mut self, x: f32, y: f32) {
@@ -54,100 +81,84 @@ impl mq::EventHandler for Stage {
     }
 }
 
-#[retained(State(mq_ctx: Box<dyn mq::RenderingBackend>))]
-fn draw(egui_mq: &mut egui_mq::EguiMq) {
-    #[retained(default)]
-    let ref mut mq_ctx: Box<dyn mq::RenderingBackend> = mq_ctx;
-    let mq_ctx = &mut **mq_ctx;
-
-    mq_ctx.clear(Some((1., 1., 1., 1.)), None, None);
-    mq_ctx.begin_default_pass(mq::PassAction::clear_color(0.0, 0.0, 0.0, 1.0));
-    mq_ctx.end_render_pass();
-
-    // Run the UI code:
-    egui_mq.run(mq_ctx, |_mq_ctx, egui_ctx| {
-        let dpi_scale = mq::window::dpi_scale();
+#[retained(DemoUi)]
+fn demo_ui(egui_ctx: &egui::Context) {
+    let dpi_scale = mq::window::dpi_scale();
 
-        #[retained(default)]
-        let ref mut show_egui_demo_windows: bool = true;
-
-        if *show_egui_demo_windows {
-            #[retained(default)]
-            let ref mut egui_demo_windows: egui_demo_lib::DemoWindows =
-                egui_demo_lib::DemoWindows::default();
+    #[retained(default)]
+    let ref mut show_egui_demo_windows: bool = true;
 
-            egui_demo_windows.ui(egui_ctx);
-        }
+    if *show_egui_demo_windows {
+        // Drop demo windows while they are hidden
+        #[retained(unmount)]
+        let ref mut egui_demo_windows: egui_demo_lib::DemoWindows =
+            egui_demo_lib::DemoWindows::default();
 
-        // zoom factor could have been changed by the user in egui using Ctrl/Cmd and -/+/0,
-        // but it could also be in the middle of being changed by us using the slider. So we
-        // only allow egui's zoom to override our zoom if the egui zoom is different from what
-        // we saw last time (meaning the user has changed it).
-        let curr_egui_zoom = egui_ctx.zoom_factor();
+        egui_demo_windows.ui(egui_ctx);
+    }
 
-        #[retained(default)]
-        let ref mut zoom_factor: f32 = 1.0;
-        if *zoom_factor != curr_egui_zoom {
-            *zoom_factor = curr_egui_zoom;
-        }
+    // zoom factor could have been changed by the user in egui using Ctrl/Cmd and -/+/0,
+    // but it could also be in the middle of being changed by us using the slider. So we
+    // only allow egui's zoom to override our zoom if the egui zoom is different from what
+    // we saw last time (meaning the user has changed it).
+    let curr_egui_zoom = egui_ctx.zoom_factor();
 
-        egui::Window::new("egui ❤ miniquad").show(egui_ctx, |ui| {
-            egui::widgets::global_dark_light_mode_buttons(ui);
-            ui.checkbox(show_egui_demo_windows, "Show egui demo windows");
-
-            ui.group(|ui| {
-                ui.label("Physical pixels per each logical 'point':");
-                ui.label(format!("native: {:.2}", dpi_scale));
-                ui.label(format!("egui:   {:.2}", ui.ctx().pixels_per_point()));
-                ui.label("Current zoom factor:");
-                ui.add(egui::Slider::new(zoom_factor, 0.75..=3.0).logarithmic(true))
-                    .on_hover_text(
-                        "Override egui zoom factor manually (changes effective pixels per point)",
-                    );
-                if ui.button("Reset").clicked() {
-                    *zoom_factor = 1.0;
-                }
-
-                ui.label("By default, egui allows zooming with\nCtrl/Cmd and +/-/0");
-                // Creating a checkbox that directly mutates the egui context's options causes a
-                // freeze so we copy the state out, possibly mutate it with the checkbox, and
-                // then copy it back in.
-                let mut zoom_with_keyboard = egui_ctx.options(|o| o.zoom_with_keyboard);
-                ui.checkbox(&mut zoom_with_keyboard, "Allow egui zoom with keyboard");
-                egui_ctx.options_mut(|o| o.zoom_with_keyboard = zoom_with_keyboard);
-            });
+    #[retained(default)]
+    let ref mut zoom_factor: f32 = 1.0;
+    if *zoom_factor != curr_egui_zoom {
+        *zoom_factor = curr_egui_zoom;
+    }
 
-            #[cfg(not(target_arch = "wasm32"))]
-            {
-                if ui.button("Quit").clicked() {
-                    std::process::exit(0);
-                }
+    egui::Window::new("egui ❤ miniquad").show(egui_ctx, |ui| {
+        egui::widgets::global_dark_light_mode_buttons(ui);
+        ui.checkbox(show_egui_demo_windows, "Show egui demo windows");
+
+        ui.group(|ui| {
+            ui.label("Physical pixels per each logical 'point':");
+            ui.label(format!("native: {:.2}", dpi_scale));
+            ui.label(format!("egui:   {:.2}", ui.ctx().pixels_per_point()));
+            ui.label("Current zoom factor:");
+            ui.add(egui::Slider::new(zoom_factor, 0.75..=3.0).logarithmic(true))
+                .on_hover_text(
+                    "Override egui zoom factor manually (changes effective pixels per point)",
+                );
+            if ui.button("Reset").clicked() {
+                *zoom_factor = 1.0;
             }
+
+            ui.label("By default, egui allows zooming with\nCtrl/Cmd and +/-/0");
+            // Creating a checkbox that directly mutates the egui context's options causes a
+            // freeze so we copy the state out, possibly mutate it with the checkbox, and
+            // then copy it back in.
+            let mut zoom_with_keyboard = egui_ctx.options(|o| o.zoom_with_keyboard);
+            ui.checkbox(&mut zoom_with_keyboard, "Allow egui zoom with keyboard");
+            egui_ctx.options_mut(|o| o.zoom_with_keyboard = zoom_with_keyboard);
         });
 
-        // Don't change zoom while dragging the slider
-        if !egui_ctx.is_using_pointer() {
-            egui_ctx.set_zoom_factor(*zoom_factor);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            if ui.button("Quit").clicked() {
+                std::process::exit(0);
+            }
         }
-
-        egui::Window::new("Color Test").show(egui_ctx, |ui| {
-            egui::ScrollArea::both()
-                .auto_shrink([false; 2])
-                .show(ui, |ui| {
-                    #[retained(default)]
-                    let ref mut color_test: egui_demo_lib::ColorTest =
-                        egui_demo_lib::ColorTest::default();
-
-                    color_test.ui(ui);
-                });
-        });
     });
 
-    // Draw things behind egui here
-    egui_mq.draw(&mut *mq_ctx);
+    // Don't change zoom while dragging the slider
+    if !egui_ctx.is_using_pointer() {
+        egui_ctx.set_zoom_factor(*zoom_factor);
+    }
+
+    egui::Window::new("Color Test").show(egui_ctx, |ui| {
+        egui::ScrollArea::both()
+            .auto_shrink([false; 2])
+            .show(ui, |ui| {
+                #[retained(default)]
+                let ref mut color_test: egui_demo_lib::ColorTest =
+                    egui_demo_lib::ColorTest::default();
 
-    // Draw things in front of egui here
-    mq_ctx.commit_frame();
+                color_test.ui(ui);
+            });
+    });
 }
 
 fn main() {
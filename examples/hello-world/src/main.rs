@@ -2,14 +2,12 @@ use std::fmt::Display;
 
 use retained::retained;
 
-#[retained(State<T>)]
+#[retained(State)]
 pub fn display<T: Display>(input: T, input2: &str) {
     #[retained]
     let ref input: T = input;
 
-    #[retained(default)]
-    let ref mut nested: State2 = State2::new("message".to_string());
-    display_str(input2, nested);
+    retained::call!(display_str(input2), "message".to_string());
 
     println!("input: {input}");
 }
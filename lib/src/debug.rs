@@ -0,0 +1,45 @@
+//! Helpers for field named [`Debug`] output of states.
+//!
+//! Values are formatted using [`Debug`] if they implement it, otherwise an opaque marker is printed.
+//! This is done by autoref specialization with [`ViaDebug`] and [`ViaOpaque`] in scope.
+
+use core::fmt::{self, Debug, Formatter};
+
+/// Formats using the closure
+pub struct DebugFn<F>(F);
+
+impl<F: Fn(&mut Formatter<'_>) -> fmt::Result> Debug for DebugFn<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        (self.0)(f)
+    }
+}
+
+/// Create [`DebugFn`], which helps inference of the closure
+pub fn debug_fn<F: Fn(&mut Formatter<'_>) -> fmt::Result>(f: F) -> DebugFn<F> {
+    DebugFn(f)
+}
+
+/// Value to format
+pub struct DebugWrap<'a, T: ?Sized>(pub &'a T);
+
+/// Format value implementing [`Debug`]
+pub trait ViaDebug {
+    fn retained_fmt(&self, f: &mut Formatter<'_>) -> fmt::Result;
+}
+
+impl<T: Debug + ?Sized> ViaDebug for DebugWrap<'_, T> {
+    fn retained_fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.0, f)
+    }
+}
+
+/// Format value not implementing [`Debug`] as opaque marker
+pub trait ViaOpaque {
+    fn retained_fmt(&self, f: &mut Formatter<'_>) -> fmt::Result;
+}
+
+impl<T: ?Sized> ViaOpaque for &DebugWrap<'_, T> {
+    fn retained_fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("<opaque>")
+    }
+}
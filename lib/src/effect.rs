@@ -0,0 +1,35 @@
+//! Storage for cleanup of effects.
+
+use alloc::boxed::Box;
+use core::fmt::{self, Debug, Formatter};
+
+/// Cleanup of an effect which runs when it is dropped
+pub struct Effect(Option<Box<dyn FnOnce()>>);
+
+impl Effect {
+    /// Run setup of the effect and store returned cleanup
+    pub fn setup<F: FnOnce() + 'static>(setup: impl FnOnce() -> F) -> Self {
+        Self(Some(Box::new(setup())))
+    }
+
+    /// Run cleanup now if it did not run yet
+    pub fn cleanup(&mut self) {
+        if let Some(cleanup) = self.0.take() {
+            cleanup();
+        }
+    }
+}
+
+impl Drop for Effect {
+    fn drop(&mut self) {
+        self.cleanup();
+    }
+}
+
+impl Debug for Effect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Effect")
+            .field("active", &self.0.is_some())
+            .finish()
+    }
+}
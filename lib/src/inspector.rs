@@ -0,0 +1,193 @@
+//! [`egui`] widget rendering generated states.
+
+use core::{any::Any, hash::Hash, mem};
+use std::{format, string::String, vec::Vec};
+
+use egui::{
+    collapsing_header::{paint_default_icon, CollapsingState},
+    CollapsingHeader, DragValue, Id, Label, Response, TextEdit, Ui, Widget,
+};
+
+use crate::{FieldInfo, RetainedState, Slot, StateVisitorMut, ValueMut, Visit};
+
+/// Widget rendering a generated state as a collapsible tree.
+///
+/// Each named retained let is shown with its value, and states stored in it are shown nested.
+/// Numbers, `bool` and `String` can be edited in place,
+/// and lazily initialized values can be reset, so they are initialized again on next access.
+/// ```no_run
+/// # use retained::{retained, Inspector};
+/// #[retained(State)]
+/// fn counter(ui: &mut egui::Ui) {
+///     #[retained]
+///     let ref mut clicks: u32 = 0;
+///     if ui.button(format!("Clicked {clicks} times")).clicked() {
+///         *clicks += 1;
+///     }
+/// }
+///
+/// fn show(ui: &mut egui::Ui, state: &mut State) {
+///     counter(ui, state);
+///
+///     #[cfg(debug_assertions)]
+///     ui.add(Inspector::new(state));
+/// }
+/// ```
+#[must_use = "You should put this widget in an ui with `ui.add(widget);`"]
+pub struct Inspector<'a, S> {
+    state: &'a mut S,
+    id_source: Option<Id>,
+    default_open: bool,
+}
+
+impl<'a, S: RetainedState> Inspector<'a, S> {
+    /// Inspect given state
+    pub fn new(state: &'a mut S) -> Self {
+        Self {
+            state,
+            id_source: None,
+            default_open: false,
+        }
+    }
+
+    /// Use given id source instead of the state name, when inspecting several states of the same type
+    pub fn id_source(mut self, id_source: impl Hash) -> Self {
+        self.id_source = Some(Id::new(id_source));
+        self
+    }
+
+    /// Open the root of the tree by default
+    pub fn default_open(mut self, default_open: bool) -> Self {
+        self.default_open = default_open;
+        self
+    }
+}
+
+impl<S: RetainedState> Widget for Inspector<'_, S> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        let id = ui.make_persistent_id(self.id_source.unwrap_or_else(|| Id::new(S::NAME)));
+
+        CollapsingHeader::new(format!("{} ({} bytes)", S::NAME, mem::size_of::<S>()))
+            .id_source(id)
+            .default_open(self.default_open)
+            .show(ui, |ui| {
+                self.state.visit_mut(&mut InspectorVisitor {
+                    ui,
+                    ids: Vec::from([id]),
+                    last: id,
+                });
+            })
+            .header_response
+    }
+}
+
+struct InspectorVisitor<'a> {
+    ui: &'a mut Ui,
+    /// Ids of entered states
+    ids: Vec<Id>,
+    /// Id of the last visited value
+    last: Id,
+}
+
+impl StateVisitorMut for InspectorVisitor<'_> {
+    fn visit(
+        &mut self,
+        field: &FieldInfo,
+        slot: Slot<'_>,
+        value: Option<&mut dyn ValueMut>,
+    ) -> Visit {
+        let name = match slot {
+            Slot::Single => String::from(field.name),
+            Slot::Iterations(indices) => indices
+                .iter()
+                .fold(String::from(field.name), |name, i| format!("{name}[{i}]")),
+            Slot::Key(key) => format!("{}[{key:?}]", field.name),
+        };
+        self.last = self.ids.last().unwrap().with(&name);
+
+        let id = self.last;
+        self.row(|ui| {
+            ui.label(name).on_hover_text(field.type_name);
+
+            let Some(value) = value else {
+                ui.weak("uninitialized");
+                return Visit::Keep;
+            };
+
+            let edited = value.as_any_mut().is_some_and(|any| edit(ui, id, any));
+            if !edited {
+                ui.add(Label::new(format!("{value:?}")).truncate(true));
+            }
+
+            if field.lazy
+                && ui
+                    .small_button("Reset")
+                    .on_hover_text("Drop the value, so it is initialized again on next access")
+                    .clicked()
+            {
+                Visit::Reset
+            } else {
+                Visit::Keep
+            }
+        })
+    }
+
+    fn enter(&mut self, name: &'static str) -> bool {
+        let id = self.last.with(name);
+        let mut state = CollapsingState::load_with_default_open(self.ui.ctx(), id, false);
+        self.row(|ui| {
+            state.show_toggle_button(ui, paint_default_icon);
+            ui.label(name);
+        });
+        state.store(self.ui.ctx());
+
+        let open = state.is_open();
+        if open {
+            self.ids.push(id);
+        }
+        open
+    }
+
+    fn leave(&mut self) {
+        self.ids.pop();
+    }
+}
+
+impl InspectorVisitor<'_> {
+    /// Show a row indented by depth of the entered state
+    fn row<R>(&mut self, add_contents: impl FnOnce(&mut Ui) -> R) -> R {
+        let indent = (self.ids.len() - 1) as f32 * self.ui.spacing().indent;
+        self.ui
+            .horizontal(|ui| {
+                ui.add_space(indent);
+                add_contents(ui)
+            })
+            .inner
+    }
+}
+
+/// Show an editor if the value is of common types, returns `false` otherwise
+fn edit(ui: &mut Ui, id: Id, value: &mut dyn Any) -> bool {
+    macro_rules! numeric {
+        ($($ty:ty),*) => {
+            $(
+                if let Some(value) = value.downcast_mut::<$ty>() {
+                    ui.add(DragValue::new(value));
+                    return true;
+                }
+            )*
+        };
+    }
+
+    numeric!(f32, f64, i8, u8, i16, u16, i32, u32, i64, u64, isize, usize);
+
+    if let Some(value) = value.downcast_mut::<bool>() {
+        ui.checkbox(value, "");
+        true
+    } else if let Some(value) = value.downcast_mut::<String>() {
+        ui.add(TextEdit::singleline(value).id_source(id));
+        true
+    } else {
+        false
+    }
+}
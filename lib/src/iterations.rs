@@ -0,0 +1,91 @@
+//! Storage for blocks with separate stack for each iteration.
+//!
+//! Slots of iterations after the cursor did not happen during the last time the block was reached.
+//! They are kept for reuse until the block is reached again, but readers only see iterations before the cursor.
+
+use alloc::vec::Vec;
+use core::{
+    fmt::{self, Debug},
+    hash::{Hash, Hasher},
+};
+
+/// Slots of a block indexed by the number of times the block was entered
+#[derive(Clone)]
+pub struct Iterations<S> {
+    slots: Vec<S>,
+    cursor: usize,
+}
+
+impl<S> Iterations<S> {
+    /// Slots of iterations that happened
+    fn entered(&self) -> &[S] {
+        &self.slots[..self.cursor]
+    }
+
+    /// Get slot of given iteration
+    pub fn get(&self, index: usize) -> Option<&S> {
+        self.entered().get(index)
+    }
+
+    /// Get slot of given iteration mutably
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut S> {
+        self.slots[..self.cursor].get_mut(index)
+    }
+
+    /// Iterate over slots of each iteration
+    pub fn iter(&self) -> impl Iterator<Item = &S> {
+        self.entered().iter()
+    }
+
+    /// Iterate over slots of each iteration mutably
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut S> {
+        self.slots[..self.cursor].iter_mut()
+    }
+}
+
+impl<S: Default> Iterations<S> {
+    /// Get slot for next iteration
+    pub fn enter(&mut self) -> &mut S {
+        if self.cursor == self.slots.len() {
+            self.slots.push(S::default());
+        }
+
+        self.cursor += 1;
+        &mut self.slots[self.cursor - 1]
+    }
+
+    /// Drop slots of iterations that did not happen and start over
+    pub fn sweep(&mut self) {
+        self.slots.truncate(self.cursor);
+        self.cursor = 0;
+    }
+}
+
+impl<S> Default for Iterations<S> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            cursor: 0,
+        }
+    }
+}
+
+impl<S: Debug> Debug for Iterations<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.entered()).finish()
+    }
+}
+
+impl<S: PartialEq> PartialEq for Iterations<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entered() == other.entered()
+    }
+}
+
+impl<S: Eq> Eq for Iterations<S> {}
+
+impl<S: Hash> Hash for Iterations<S> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.entered().hash(state);
+    }
+}
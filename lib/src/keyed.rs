@@ -0,0 +1,232 @@
+//! Storages for keyed retained lets.
+//!
+//! Every entry has a flag marking it was touched during current call.
+//! Entries untouched during a call are evicted on the beginning of the next call.
+
+use core::hash::{Hash, Hasher};
+#[cfg(feature = "std")]
+use std::collections::{hash_map::Entry, HashMap};
+
+#[cfg(feature = "std")]
+/// Keyed storage backed by [`HashMap`]
+#[derive(Debug, Clone)]
+pub struct Keyed<K, V> {
+    map: HashMap<K, (V, bool)>,
+}
+
+#[cfg(feature = "std")]
+impl<K: Hash + Eq, V> Keyed<K, V> {
+    /// Mark entry as touched, returns `true` if the entry exists
+    pub fn touch(&mut self, key: &K) -> bool {
+        match self.map.get_mut(key) {
+            Some((_, touched)) => {
+                *touched = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get entry
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key).map(|(value, _)| value)
+    }
+
+    /// Get entry mutably
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.map.get_mut(key).map(|(value, _)| value)
+    }
+
+    /// Iterate over entries
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.map.iter().map(|(key, (value, _))| (key, value))
+    }
+
+    /// Keep entries for which the closure returns `true`
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        self.map.retain(|key, (value, _)| f(key, value));
+    }
+
+    /// Insert new touched entry
+    pub fn insert(&mut self, key: K, value: V) -> &mut V {
+        match self.map.entry(key) {
+            Entry::Occupied(mut entry) => {
+                entry.insert((value, true));
+                &mut entry.into_mut().0
+            }
+            Entry::Vacant(entry) => &mut entry.insert((value, true)).0,
+        }
+    }
+
+    /// Evict untouched entries and start new call
+    pub fn sweep(&mut self) {
+        self.map.retain(|_, (_, touched)| core::mem::take(touched));
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: Hash + Eq, V: PartialEq> PartialEq for Keyed<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.map == other.map
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: Hash + Eq, V: Eq> Eq for Keyed<K, V> {}
+
+#[cfg(feature = "std")]
+impl<K: Hash + Eq, V: Hash> Hash for Keyed<K, V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_unordered(self.map.iter(), state);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V> Default for Keyed<K, V> {
+    fn default() -> Self {
+        Self {
+            map: HashMap::new(),
+        }
+    }
+}
+
+/// Keyed storage with fixed capacity
+#[derive(Debug, Clone)]
+pub struct FixedKeyed<K, V, const N: usize> {
+    entries: [Option<(K, V, bool)>; N],
+}
+
+impl<K: Eq, V, const N: usize> FixedKeyed<K, V, N> {
+    fn position(&self, key: &K) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| matches!(entry, Some((entry_key, _, _)) if entry_key == key))
+    }
+
+    /// Mark entry as touched, returns `true` if the entry exists
+    pub fn touch(&mut self, key: &K) -> bool {
+        match self.position(key) {
+            Some(index) => {
+                self.entries[index].as_mut().unwrap().2 = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get entry
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let index = self.position(key)?;
+        Some(&self.entries[index].as_ref()?.1)
+    }
+
+    /// Get entry mutably
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let index = self.position(key)?;
+        Some(&mut self.entries[index].as_mut()?.1)
+    }
+
+    /// Iterate over entries
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|(key, value, _)| (key, value))
+    }
+
+    /// Keep entries for which the closure returns `true`
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        for entry in &mut self.entries {
+            if let Some((key, value, _)) = entry {
+                if !f(key, value) {
+                    *entry = None;
+                }
+            }
+        }
+    }
+
+    /// Insert new touched entry
+    ///
+    /// # Panics
+    /// Panics if there is no vacant entry
+    pub fn insert(&mut self, key: K, value: V) -> &mut V {
+        let index = self
+            .position(&key)
+            .or_else(|| self.entries.iter().position(Option::is_none))
+            .expect("keyed retained let exceeded its capacity");
+
+        &mut self.entries[index].insert((key, value, true)).1
+    }
+
+    /// Evict untouched entries and start new call
+    pub fn sweep(&mut self) {
+        for entry in &mut self.entries {
+            match entry {
+                Some((_, _, touched)) if *touched => *touched = false,
+                _ => *entry = None,
+            }
+        }
+    }
+}
+
+impl<K, V, const N: usize> Default for FixedKeyed<K, V, N> {
+    fn default() -> Self {
+        Self {
+            entries: [const { None }; N],
+        }
+    }
+}
+
+impl<K: Eq, V: PartialEq, const N: usize> PartialEq for FixedKeyed<K, V, N> {
+    fn eq(&self, other: &Self) -> bool {
+        // Entries are compared by key, regardless of their slots
+        self.entries.iter().flatten().count() == other.entries.iter().flatten().count()
+            && self.entries.iter().flatten().all(|(key, value, touched)| {
+                other.position(key).is_some_and(|index| {
+                    matches!(&other.entries[index], Some((_, other_value, other_touched))
+                        if other_value == value && other_touched == touched)
+                })
+            })
+    }
+}
+
+impl<K: Eq, V: Eq, const N: usize> Eq for FixedKeyed<K, V, N> {}
+
+impl<K: Hash, V: Hash, const N: usize> Hash for FixedKeyed<K, V, N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_unordered(self.entries.iter().flatten(), state);
+    }
+}
+
+/// Hash entries regardless of their order, by combining their hashes with sum
+fn hash_unordered<T: Hash, H: Hasher>(entries: impl Iterator<Item = T>, state: &mut H) {
+    let (len, sum) = entries.fold((0usize, 0u64), |(len, sum), entry| {
+        let mut hasher = EntryHasher::default();
+        entry.hash(&mut hasher);
+        (len + 1, sum.wrapping_add(hasher.finish()))
+    });
+
+    state.write_usize(len);
+    state.write_u64(sum);
+}
+
+/// Deterministic FNV-1a hasher for single entries, available without `std`
+struct EntryHasher(u64);
+
+impl Default for EntryHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for EntryHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
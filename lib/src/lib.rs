@@ -1,4 +1,112 @@
 #![no_std]
 #![doc = include_str!("../README.md")]
 
-pub use retained_macro::retained;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+mod debug;
+#[cfg(feature = "alloc")]
+mod effect;
+#[cfg(feature = "egui")]
+mod inspector;
+#[cfg(feature = "alloc")]
+mod iterations;
+mod keyed;
+mod mount;
+mod reflect;
+mod retained_fn;
+#[cfg(feature = "serde")]
+mod serde_impls;
+
+pub use reflect::{
+    FieldInfo, RetainedState, Slot, StateVisitor, StateVisitorMut, Value, ValueMut, Visit,
+};
+pub use retained_fn::{RetainedFn, WithState};
+pub use retained_macro::{closure, retained};
+
+#[cfg(feature = "egui")]
+pub use inspector::Inspector;
+
+/// Run an effect when its dependencies change inside of a `#[retained]` function.
+///
+/// The first argument is a parenthesized list of dependencies like the `memo` option,
+/// and the second is a closure running setup and returning its cleanup.
+/// The cleanup runs before the next setup, or when the state is dropped or reset.
+/// Requires `alloc` feature.
+/// ```
+/// # use retained::retained;
+/// #[retained(State)]
+/// fn watch(path: &str) {
+///     retained::effect!((path), || {
+///         println!("watch {path}");
+///         move || println!("unwatch")
+///     });
+/// }
+/// ```
+#[macro_export]
+macro_rules! effect {
+    ($($tt:tt)*) => {
+        ::core::compile_error!(
+            "`effect!` can only be used as `retained::effect!` statement inside of `#[retained]` function"
+        )
+    };
+}
+
+/// Call another `#[retained]` function inside of a `#[retained]` function,
+/// storing state of the callee in the state of the caller.
+///
+/// The first argument is the call without the state argument, optionally preceded by the callee's state type and `=>`,
+/// and the rest are constructor arguments of the callee's state, evaluated on first call.
+/// The callee must take its state as the last argument, so it cannot have `position` or `from` option.
+/// ```
+/// # use retained::retained;
+/// #[retained(State)]
+/// fn greet(name: &str) {
+///     retained::call!(print(name), String::from("Hello"));
+///     retained::call!(PrintState => print(name), String::from("Bye"));
+/// }
+///
+/// #[retained(PrintState(greeting: String))]
+/// fn print(name: &str) {
+///     #[retained(default)]
+///     let ref greeting: String = greeting;
+///     println!("{greeting} {name}");
+/// }
+/// ```
+#[macro_export]
+macro_rules! call {
+    ($($tt:tt)*) => {
+        ::core::compile_error!(
+            "`call!` can only be used as `retained::call!` inside of `#[retained]` function"
+        )
+    };
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::debug::{debug_fn, DebugFn, DebugWrap, ViaDebug, ViaOpaque};
+
+    #[cfg(feature = "alloc")]
+    pub use crate::effect::Effect;
+    #[cfg(feature = "alloc")]
+    pub use crate::iterations::Iterations;
+    #[cfg(feature = "alloc")]
+    pub use alloc::borrow::ToOwned;
+
+    pub use crate::keyed::FixedKeyed;
+    pub use crate::mount::Mount;
+    pub use crate::reflect::{
+        visit_value, visit_value_mut, Probe, ProbeAny, ProbeAnyMut, ProbeDebug, ProbeName,
+        ProbeNoAny, ProbeNoAnyMut, ProbeNoDebug, ProbeNoName, ProbeNoVisit, ProbeNoVisitMut,
+        ProbeVisit, ProbeVisitMut, VTable,
+    };
+    pub use crate::retained_fn::Callee;
+
+    #[cfg(feature = "std")]
+    pub use crate::keyed::Keyed;
+
+    #[cfg(feature = "serde")]
+    pub use serde;
+}
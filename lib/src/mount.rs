@@ -0,0 +1,35 @@
+//! Storage for blocks unmounted when they stop executing.
+
+/// Slot of a block remembering the last call entered the block
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Mount<S> {
+    slot: S,
+    call: u32,
+}
+
+impl<S> Mount<S> {
+    /// Get slot
+    pub fn get(&self) -> &S {
+        &self.slot
+    }
+
+    /// Get slot mutably
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.slot
+    }
+}
+
+impl<S: Default> Mount<S> {
+    /// Get slot on block entry during given call
+    pub fn enter(&mut self, call: u32) -> &mut S {
+        self.call = call;
+        &mut self.slot
+    }
+
+    /// Drop slot if the block was not entered during previous or given call
+    pub fn sweep(&mut self, call: u32) {
+        if call.wrapping_sub(self.call) > 1 {
+            self.slot = S::default();
+        }
+    }
+}
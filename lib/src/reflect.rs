@@ -0,0 +1,314 @@
+//! Runtime reflection of generated states.
+//!
+//! Capabilities of each retained let are detected by autoref specialization on [`Probe`],
+//! so values not implementing [`Debug`] or not `'static` can still be visited.
+
+use core::{
+    any::Any,
+    fmt::{self, Debug, Formatter},
+    marker::PhantomData,
+};
+
+/// Named retained let of a state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FieldInfo {
+    /// Binding name
+    pub name: &'static str,
+    /// Type of the variable as written
+    pub type_name: &'static str,
+    /// Variable is initialized on access and can be reset, rather than initialized with the state
+    pub lazy: bool,
+}
+
+/// State generated by `#[retained]`
+///
+/// ```
+/// # use retained::{retained, FieldInfo, RetainedState, Slot, StateVisitor, Value};
+/// #[retained(State)]
+/// fn draw() {
+///     #[retained]
+///     let ref mut clicks: u32 = 0;
+///     *clicks += 1;
+/// }
+///
+/// struct Print;
+///
+/// impl StateVisitor for Print {
+///     fn visit(&mut self, field: &FieldInfo, _: Slot<'_>, value: Option<&dyn Value>) {
+///         println!("{}: {:?}", field.name, value);
+///     }
+/// }
+///
+/// let mut state = State::new();
+/// draw(&mut state);
+///
+/// assert_eq!(State::FIELD_COUNT, 1);
+/// assert_eq!(State::FIELDS[0].name, "clicks");
+/// state.visit(&mut Print);
+/// ```
+pub trait RetainedState {
+    /// Name of the state
+    const NAME: &'static str;
+
+    /// Number of named retained lets
+    const FIELD_COUNT: usize;
+
+    /// Named retained lets in the order they are visited
+    const FIELDS: &'static [FieldInfo];
+
+    /// Visit each value of named retained lets, and fields of states stored in them
+    fn visit(&self, visitor: &mut dyn StateVisitor);
+
+    /// Visit each value of named retained lets mutably, and fields of states stored in them
+    fn visit_mut(&mut self, visitor: &mut dyn StateVisitorMut);
+}
+
+/// Location of a visited value in its retained let
+#[derive(Debug, Clone, Copy)]
+pub enum Slot<'a> {
+    /// The only value
+    Single,
+    /// Value in `per_iteration` blocks, indexed by each iteration from the outermost
+    Iterations(&'a [usize]),
+    /// Value of the key in a keyed let
+    Key(&'a dyn Debug),
+}
+
+/// Visited value, formatted as `<opaque>` if it does not implement [`Debug`]
+pub trait Value: Debug {
+    /// Value as [`Any`], `None` if it is not `'static`
+    fn as_any(&self) -> Option<&dyn Any>;
+}
+
+/// Mutably visited value
+pub trait ValueMut: Value {
+    /// Value as [`Any`], `None` if it is not `'static`
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any>;
+}
+
+/// Visitor of retained lets
+pub trait StateVisitor {
+    /// Visit a value, `None` if it is not initialized
+    fn visit(&mut self, field: &FieldInfo, slot: Slot<'_>, value: Option<&dyn Value>);
+
+    /// Called before visiting fields of a state stored in the last visited value.
+    /// Returning `false` skips them.
+    fn enter(&mut self, name: &'static str) -> bool {
+        _ = name;
+        true
+    }
+
+    /// Called after visiting fields of a state entered
+    fn leave(&mut self) {}
+}
+
+/// Mutable visitor of retained lets
+pub trait StateVisitorMut {
+    /// Visit a value, `None` if it is not initialized
+    fn visit(
+        &mut self,
+        field: &FieldInfo,
+        slot: Slot<'_>,
+        value: Option<&mut dyn ValueMut>,
+    ) -> Visit;
+
+    /// Called before visiting fields of a state stored in the last visited value.
+    /// Returning `false` skips them.
+    fn enter(&mut self, name: &'static str) -> bool {
+        _ = name;
+        true
+    }
+
+    /// Called after visiting fields of a state entered
+    fn leave(&mut self) {}
+}
+
+/// Action after mutably visiting a value
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Visit {
+    /// Keep the value
+    #[default]
+    Keep,
+    /// Drop the value, so it is initialized again on next access.
+    /// Ignored for variables not [`lazy`](FieldInfo::lazy).
+    Reset,
+}
+
+/// Functions of a type chosen by [`Probe`]
+pub struct VTable<T> {
+    pub name: Option<&'static str>,
+    pub debug: fn(&T, &mut Formatter<'_>) -> fmt::Result,
+    pub any: fn(&T) -> Option<&dyn Any>,
+    pub any_mut: fn(&mut T) -> Option<&mut dyn Any>,
+    pub visit: Option<fn(&T, &mut dyn StateVisitor)>,
+    pub visit_mut: Option<fn(&mut T, &mut dyn StateVisitorMut)>,
+}
+
+impl<T> Clone for VTable<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for VTable<T> {}
+
+/// Type to probe capabilities of
+pub struct Probe<T>(PhantomData<fn() -> T>);
+
+impl<T> Probe<T> {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+macro_rules! probe {
+    ($name:ident, $fallback:ident, $method:ident -> $ret:ty, [$($bound:tt)*] => $found:expr, $missing:expr) => {
+        pub trait $name<T> {
+            fn $method(&self) -> $ret;
+        }
+
+        impl<T: $($bound)*> $name<T> for Probe<T> {
+            fn $method(&self) -> $ret {
+                $found
+            }
+        }
+
+        pub trait $fallback<T> {
+            fn $method(&self) -> $ret;
+        }
+
+        impl<T> $fallback<T> for &Probe<T> {
+            fn $method(&self) -> $ret {
+                $missing
+            }
+        }
+    };
+}
+
+probe!(
+    ProbeDebug, ProbeNoDebug, retained_debug -> fn(&T, &mut Formatter<'_>) -> fmt::Result,
+    [Debug] => <T as Debug>::fmt,
+    |_, f| f.write_str("<opaque>")
+);
+probe!(
+    ProbeAny, ProbeNoAny, retained_any -> fn(&T) -> Option<&dyn Any>,
+    [Any] => |value| Some(value as &dyn Any),
+    |_| None
+);
+probe!(
+    ProbeAnyMut, ProbeNoAnyMut, retained_any_mut -> fn(&mut T) -> Option<&mut dyn Any>,
+    [Any] => |value| Some(value as &mut dyn Any),
+    |_| None
+);
+probe!(
+    ProbeName, ProbeNoName, retained_name -> Option<&'static str>,
+    [RetainedState] => Some(T::NAME),
+    None
+);
+probe!(
+    ProbeVisit, ProbeNoVisit, retained_visit -> Option<fn(&T, &mut dyn StateVisitor)>,
+    [RetainedState] => Some(T::visit),
+    None
+);
+probe!(
+    ProbeVisitMut, ProbeNoVisitMut, retained_visit_mut -> Option<fn(&mut T, &mut dyn StateVisitorMut)>,
+    [RetainedState] => Some(T::visit_mut),
+    None
+);
+
+struct Erased<'a, T> {
+    value: &'a T,
+    vtable: VTable<T>,
+}
+
+impl<T> Debug for Erased<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        (self.vtable.debug)(self.value, f)
+    }
+}
+
+impl<T> Value for Erased<'_, T> {
+    fn as_any(&self) -> Option<&dyn Any> {
+        (self.vtable.any)(self.value)
+    }
+}
+
+struct ErasedMut<'a, T> {
+    value: &'a mut T,
+    vtable: VTable<T>,
+}
+
+impl<T> Debug for ErasedMut<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        (self.vtable.debug)(self.value, f)
+    }
+}
+
+impl<T> Value for ErasedMut<'_, T> {
+    fn as_any(&self) -> Option<&dyn Any> {
+        (self.vtable.any)(self.value)
+    }
+}
+
+impl<T> ValueMut for ErasedMut<'_, T> {
+    fn as_any_mut(&mut self) -> Option<&mut dyn Any> {
+        (self.vtable.any_mut)(self.value)
+    }
+}
+
+/// Visit a value, entering the state stored in it
+pub fn visit_value<T>(
+    visitor: &mut dyn StateVisitor,
+    field: &FieldInfo,
+    slot: Slot<'_>,
+    value: Option<&T>,
+    vtable: VTable<T>,
+) {
+    let Some(value) = value else {
+        visitor.visit(field, slot, None);
+        return;
+    };
+
+    visitor.visit(field, slot, Some(&Erased { value, vtable }));
+    if let (Some(name), Some(visit)) = (vtable.name, vtable.visit) {
+        if visitor.enter(name) {
+            visit(value, visitor);
+            visitor.leave();
+        }
+    }
+}
+
+/// Visit a value mutably, entering the state stored in it
+pub fn visit_value_mut<T>(
+    visitor: &mut dyn StateVisitorMut,
+    field: &FieldInfo,
+    slot: Slot<'_>,
+    value: Option<&mut T>,
+    vtable: VTable<T>,
+) -> Visit {
+    let Some(value) = value else {
+        return visitor.visit(field, slot, None);
+    };
+
+    let visit = visitor.visit(
+        field,
+        slot,
+        Some(&mut ErasedMut {
+            value: &mut *value,
+            vtable,
+        }),
+    );
+    if visit == Visit::Reset && field.lazy {
+        return visit;
+    }
+
+    if let (Some(name), Some(visit_mut)) = (vtable.name, vtable.visit_mut) {
+        if visitor.enter(name) {
+            visit_mut(value, visitor);
+            visitor.leave();
+        }
+    }
+
+    Visit::Keep
+}
@@ -0,0 +1,108 @@
+//! Generic calls of `#[retained]` functions.
+
+use core::{
+    fmt::{self, Debug, Formatter},
+    marker::PhantomData,
+};
+
+/// `#[retained]` function called with its state and a tuple of arguments.
+///
+/// `marker` option of a `#[retained]` function taking its state as the last argument declares a marker type implementing this trait.
+/// The marker takes generic parameters of the state, and it is not implemented for `async` functions,
+/// `unsafe` functions, or functions with `impl Trait` in their signature or generic parameters used only by the return type.
+/// ```
+/// # use retained::{retained, RetainedFn};
+/// #[retained(State, marker = Count)]
+/// fn count(step: u32) -> u32 {
+///     #[retained]
+///     let ref mut count: u32 = 0;
+///     *count += step;
+///     *count
+/// }
+///
+/// fn call_twice<F: RetainedFn<(u32,), Output = u32>>(state: &mut F::State) -> u32 {
+///     F::call(state, (1,));
+///     F::call(state, (2,))
+/// }
+///
+/// let mut state = State::new();
+/// assert_eq!(call_twice::<Count>(&mut state), 3);
+/// ```
+pub trait RetainedFn<Args> {
+    /// State of the function
+    type State;
+
+    /// Return type of the function
+    type Output;
+
+    /// Call the function with its state
+    fn call(state: &mut Self::State, args: Args) -> Self::Output;
+}
+
+/// `#[retained]` function paired with its state, so it can be stored and called without the state at hand.
+/// ```
+/// # use retained::{retained, WithState};
+/// #[retained(State, marker = Log)]
+/// fn log(message: &str) -> usize {
+///     #[retained]
+///     let ref mut lines: Vec<String> = Vec::new();
+///     lines.push(message.to_string());
+///     lines.len()
+/// }
+///
+/// let mut log = WithState::<Log, _>::new(State::new());
+/// log.call(("first",));
+/// assert_eq!(log.call(("second",)), 2);
+///
+/// // Store with other functions taking same arguments
+/// let mut tasks: Vec<Box<dyn FnMut(&str) -> usize>> = Vec::new();
+/// tasks.push(Box::new(move |message| log.call((message,))));
+/// ```
+pub struct WithState<F, S> {
+    /// State of the function
+    pub state: S,
+    f: PhantomData<fn() -> F>,
+}
+
+impl<F, S> WithState<F, S> {
+    /// Pair the function with given state
+    pub fn new(state: S) -> Self {
+        Self {
+            state,
+            f: PhantomData,
+        }
+    }
+
+    /// Call the function with the state
+    pub fn call<Args>(&mut self, args: Args) -> F::Output
+    where
+        F: RetainedFn<Args, State = S>,
+    {
+        F::call(&mut self.state, args)
+    }
+
+    /// Take the state back
+    pub fn into_state(self) -> S {
+        self.state
+    }
+}
+
+impl<F, S: Clone> Clone for WithState<F, S> {
+    fn clone(&self) -> Self {
+        Self::new(self.state.clone())
+    }
+}
+
+impl<F, S: Debug> Debug for WithState<F, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithState")
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+/// Type finding the state of a `#[retained]` function, so `call!` can store it.
+/// Implemented by states themselves and marker types.
+pub trait Callee {
+    type State;
+}
@@ -0,0 +1,99 @@
+//! Serialization of keyed storages as maps.
+//!
+//! Deserialized entries are marked as touched, so they are kept until the end of the next call.
+
+use core::{fmt, marker::PhantomData};
+
+use serde::{
+    de::{Error, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::keyed::FixedKeyed;
+
+#[cfg(feature = "std")]
+use crate::keyed::Keyed;
+
+#[cfg(feature = "std")]
+impl<K: Serialize + core::hash::Hash + Eq, V: Serialize> Serialize for Keyed<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de, K, V> Deserialize<'de> for Keyed<K, V>
+where
+    K: Deserialize<'de> + core::hash::Hash + Eq,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyedVisitor<K, V>(PhantomData<fn() -> (K, V)>);
+
+        impl<'de, K, V> Visitor<'de> for KeyedVisitor<K, V>
+        where
+            K: Deserialize<'de> + core::hash::Hash + Eq,
+            V: Deserialize<'de>,
+        {
+            type Value = Keyed<K, V>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut keyed = Keyed::default();
+                while let Some((key, value)) = map.next_entry()? {
+                    keyed.insert(key, value);
+                }
+
+                Ok(keyed)
+            }
+        }
+
+        deserializer.deserialize_map(KeyedVisitor(PhantomData))
+    }
+}
+
+impl<K: Serialize + Eq, V: Serialize, const N: usize> Serialize for FixedKeyed<K, V, N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de, K, V, const N: usize> Deserialize<'de> for FixedKeyed<K, V, N>
+where
+    K: Deserialize<'de> + Eq,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FixedKeyedVisitor<K, V, const N: usize>(PhantomData<fn() -> (K, V)>);
+
+        impl<'de, K, V, const N: usize> Visitor<'de> for FixedKeyedVisitor<K, V, N>
+        where
+            K: Deserialize<'de> + Eq,
+            V: Deserialize<'de>,
+        {
+            type Value = FixedKeyed<K, V, N>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map with at most {N} entries")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut keyed = FixedKeyed::default();
+                while let Some((key, value)) = map.next_entry()? {
+                    if !keyed.touch(&key) && keyed.iter().count() == N {
+                        return Err(A::Error::invalid_length(N + 1, &self));
+                    }
+
+                    keyed.insert(key, value);
+                }
+
+                Ok(keyed)
+            }
+        }
+
+        deserializer.deserialize_map(FixedKeyedVisitor(PhantomData))
+    }
+}
@@ -0,0 +1,216 @@
+use syn::{
+    parenthesized,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    token::Comma,
+    Expr, FnArg, Ident, LitInt, Pat, Path, Signature, Token,
+};
+
+use crate::state::StateDecl;
+
+/// Arguments of `retained` attribute on function
+pub struct RetainedAttr {
+    pub decl: StateDecl,
+    /// Place expression to borrow state from instead of additional argument
+    pub from: Option<Expr>,
+    /// Position of additional state argument
+    pub position: Option<StatePosition>,
+    /// Binding of whole state argument
+    pub name: Option<Ident>,
+    /// Unmount every nested block
+    pub unmount: bool,
+    /// Generate accessors of named retained lets
+    pub accessors: bool,
+    /// Print named retained lets in `Debug` output
+    pub debug: bool,
+    /// Derives applied to state structs
+    pub derives: Punctuated<Path, Comma>,
+    /// Implement serde traits for the state
+    pub serde: bool,
+    /// Name of marker type implementing `RetainedFn`
+    pub marker: Option<Ident>,
+}
+
+impl RetainedAttr {
+    /// Function takes the state as the last argument, so it can be called with `call!`
+    pub fn callable(&self) -> bool {
+        self.from.is_none() && self.position.is_none()
+    }
+}
+
+impl Parse for RetainedAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let decl = input.parse()?;
+        let mut from = None;
+        let mut position = None;
+        let mut name = None;
+        let mut unmount = None;
+        let mut accessors = None;
+        let mut debug = None;
+        let mut derives = None;
+        let mut serde = None;
+        let mut marker = None;
+
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+
+            let option: Ident = input.parse()?;
+            if option == "from" {
+                input.parse::<Token![=]>()?;
+                set_option(&mut from, &option, input.parse()?)?;
+            } else if option == "position" {
+                input.parse::<Token![=]>()?;
+                set_option(&mut position, &option, input.parse()?)?;
+            } else if option == "name" {
+                input.parse::<Token![=]>()?;
+                set_option(&mut name, &option, input.parse()?)?;
+            } else if option == "unmount" {
+                set_option(&mut unmount, &option, ())?;
+            } else if option == "accessors" {
+                set_option(&mut accessors, &option, ())?;
+            } else if option == "debug" {
+                set_option(&mut debug, &option, ())?;
+            } else if option == "derive" {
+                let content;
+                _ = parenthesized!(content in input);
+                let paths = Punctuated::<Path, Comma>::parse_terminated(&content)?;
+                if let Some(debug) = paths.iter().find(|path| path.is_ident("Debug")) {
+                    return Err(syn::Error::new_spanned(
+                        debug,
+                        "`Debug` is always implemented, use `debug` option to print fields",
+                    ));
+                }
+
+                set_option(&mut derives, &option, paths)?;
+            } else if option == "serde" {
+                if !cfg!(feature = "serde") {
+                    return Err(syn::Error::new_spanned(
+                        &option,
+                        "`serde` option requires `serde` feature of `retained`",
+                    ));
+                }
+
+                set_option(&mut serde, &option, ())?;
+            } else if option == "marker" {
+                input.parse::<Token![=]>()?;
+                set_option(&mut marker, &option, input.parse()?)?;
+            } else {
+                return Err(syn::Error::new_spanned(
+                    &option,
+                    format!("unknown option `{option}`"),
+                ));
+            }
+        }
+
+        if from.is_some() && (position.is_some() || name.is_some()) {
+            return Err(input.error("`from` cannot be used with `position` or `name`"));
+        }
+
+        if let Some(ref marker) = marker {
+            if from.is_some() || position.is_some() {
+                return Err(syn::Error::new_spanned(
+                    marker,
+                    "`marker` requires the state as the last argument, so it cannot be used with `from` or `position`",
+                ));
+            }
+        }
+
+        Ok(Self {
+            decl,
+            from,
+            position,
+            name,
+            unmount: unmount.is_some(),
+            accessors: accessors.is_some(),
+            debug: debug.is_some(),
+            derives: derives.unwrap_or_default(),
+            serde: serde.is_some(),
+            marker,
+        })
+    }
+}
+
+pub enum StatePosition {
+    First,
+    Last,
+    Index(LitInt),
+    Before(Ident),
+    After(Ident),
+}
+
+impl StatePosition {
+    /// Index of state argument in `sig.inputs`
+    pub fn index(&self, sig: &Signature) -> syn::Result<usize> {
+        let receiver = usize::from(sig.receiver().is_some());
+
+        Ok(match self {
+            Self::First => receiver,
+            Self::Last => sig.inputs.len(),
+            Self::Index(lit) => {
+                let index = receiver + lit.base10_parse::<usize>()?;
+                if index > sig.inputs.len() {
+                    return Err(syn::Error::new_spanned(lit, "position out of bounds"));
+                }
+
+                index
+            }
+            Self::Before(name) => find_arg(sig, name)?,
+            Self::After(name) => find_arg(sig, name)? + 1,
+        })
+    }
+}
+
+impl Parse for StatePosition {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(LitInt) {
+            return Ok(Self::Index(input.parse()?));
+        }
+
+        let ident: Ident = input.parse()?;
+        if ident == "first" {
+            Ok(Self::First)
+        } else if ident == "last" {
+            Ok(Self::Last)
+        } else if ident == "before" || ident == "after" {
+            let content;
+            _ = parenthesized!(content in input);
+            let name = content.parse()?;
+
+            Ok(if ident == "before" {
+                Self::Before(name)
+            } else {
+                Self::After(name)
+            })
+        } else {
+            Err(syn::Error::new_spanned(
+                ident,
+                "expected `first`, `last`, index, `before(arg)` or `after(arg)`",
+            ))
+        }
+    }
+}
+
+fn find_arg(sig: &Signature, name: &Ident) -> syn::Result<usize> {
+    sig.inputs
+        .iter()
+        .position(|arg| match arg {
+            FnArg::Typed(arg) => matches!(*arg.pat, Pat::Ident(ref pat) if pat.ident == *name),
+            FnArg::Receiver(_) => false,
+        })
+        .ok_or_else(|| syn::Error::new_spanned(name, format!("cannot find argument `{name}`")))
+}
+
+fn set_option<T>(slot: &mut Option<T>, option: &Ident, value: T) -> syn::Result<()> {
+    if slot.is_some() {
+        return Err(syn::Error::new_spanned(
+            option,
+            format!("duplicate option `{option}`"),
+        ));
+    }
+
+    *slot = Some(value);
+    Ok(())
+}
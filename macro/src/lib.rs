@@ -1,27 +1,27 @@
+mod attr;
+mod lift;
+mod marker;
+mod reflect;
 mod retained_let;
+mod serde_impls;
 mod state;
 
+use attr::RetainedAttr;
 use proc_macro::TokenStream;
 use proc_macro2::Span;
-use quote::quote_spanned;
-use retained_let::RetainedLetExpander;
+use quote::{quote, quote_spanned, ToTokens};
+use retained_let::{typed_args, RetainedLetExpander};
 use state::{State, StateArg, StateDecl};
-use syn::{parse_macro_input, parse_quote, Ident, ItemFn};
+use syn::{
+    parse_macro_input, parse_quote, parse_quote_spanned, punctuated::Punctuated, visit::Visit,
+    Attribute, Block, Expr, ExprBlock, ExprClosure, FnArg, GenericParam, Generics, Ident, ImplItem,
+    Item, ItemImpl, ItemMod, Lifetime, LifetimeParam, Pat, Signature, Visibility,
+};
 
 /// Create external storage for tagged local variables and bind to original let statement.
 ///
-/// This macro cannot be used inside impl block.
-/// ```compile_fail
-/// impl Struct {
-///     #[retained(State)]
-///     pub fn method(&self) {
-///         ..
-///     }
-/// }
-/// ```
-///
 /// ## Usage
-/// The `retained` macro can only be used on top of bare function.
+/// The `retained` macro can be used on top of bare function.
 /// It takes identifier and optionally generics paremeters to build state struct declaration.
 /// The macro will make a storage for local variables tagged with `#[retained]`.
 ///
@@ -30,44 +30,1138 @@ use syn::{parse_macro_input, parse_quote, Ident, ItemFn};
 ///
 /// The following does not compile as it will move state's field to local variable.
 /// ```compile_fail
+/// # use retained::retained;
 /// #[retained(State)]
 /// fn my_fn() {
 ///     #[retained]
-///     let retained_string: String = String::new("");
+///     let retained_string: String = String::from("");
 /// }
 /// ```
 ///
 /// To make this work, use ref pattern instead.
 /// ```no_run
+/// # use retained::retained;
 /// #[retained(State)]
 /// fn my_fn() {
 ///     #[retained]
-///     let ref retained_string: String = String::new("");
+///     let ref retained_string: String = String::from("");
 ///     // Mutable access
-///     // let ref mut retained_string: String = String::new("");
+///     // let ref mut retained_string: String = String::from("");
+/// }
+/// ```
+///
+/// ## Generics
+/// State declaration takes generic parameters like a struct, with bounds, defaults, const generics and a `where` clause.
+/// The state argument refers to the parameters, so the function declares them too.
+/// Parameters not used by any retained let are allowed.
+/// ```
+/// # use retained::retained;
+/// use std::fmt::Display;
+///
+/// #[retained(State<T: Display = u32, const N: usize = 4>(fill: T) where T: Clone)]
+/// fn show<T: Display + Clone, const N: usize>() {
+///     #[retained(default)]
+///     let ref items: [T; N] = std::array::from_fn(|_| fill.clone());
+///     for item in items {
+///         print!("{item} ");
+///     }
+/// }
+///
+/// // `State<u32, 4>`
+/// let mut state: State = State::new(1);
+/// show(&mut state);
+/// ```
+///
+/// A state declared without generics gets generic parameters of the function
+/// used by types of retained lets or constructor arguments, with their bounds.
+/// Parameters cannot be lifted from functions sharing a state, so it must declare them.
+/// ```
+/// # use retained::retained;
+/// #[retained(State)]
+/// fn remember<T: Clone>(value: &T) -> T {
+///     #[retained]
+///     let ref first: T = value.clone();
+///     first.clone()
+/// }
+///
+/// let mut state = State::<u32>::new();
+/// assert_eq!(remember(&1, &mut state), 1);
+/// assert_eq!(remember(&2, &mut state), 1);
+/// ```
+///
+/// Lifetime parameters let retained lets borrow data outliving every call, like an input of the function.
+/// Lifetimes in types of retained lets cannot be elided, as the state has no lifetime to infer them from.
+/// ```
+/// # use retained::retained;
+/// use std::str::Lines;
+///
+/// #[retained(State)]
+/// fn next_line<'a>(text: &'a str) -> Option<&'a str> {
+///     #[retained]
+///     let ref mut lines: Lines<'a> = text.lines();
+///     lines.next()
+/// }
+///
+/// let text = String::from("first\nsecond");
+/// let mut state = State::new();
+/// assert_eq!(next_line(&text, &mut state), Some("first"));
+/// assert_eq!(next_line(&text, &mut state), Some("second"));
+/// ```
+///
+/// ## Per iteration
+/// Retained lets are stored in a stack for each block and nested blocks are stored inside of it.
+/// `per_iteration` gives the block a separate stack for each time it is entered,
+/// so the Nth iteration of a loop or the Nth call of a closure gets the Nth stack.
+/// Stacks of iterations that did not happen during the last time the block was reached are hidden from accessors,
+/// `Debug`, serialization and reflection, and they are dropped next time the block is reached.
+/// It applies to the whole block containing the let and requires `alloc` feature.
+/// ```
+/// # use retained::retained;
+/// #[retained(State, debug, accessors)]
+/// fn rows(count: usize) {
+///     for i in 0..count {
+///         #[retained(per_iteration)]
+///         let ref mut expanded: bool = i == 0;
+///
+///         if *expanded {
+///             #[retained]
+///             let ref mut text: String = format!("row {i}");
+///             println!("{text}");
+///         }
+///     }
+/// }
+///
+/// let mut state = State::new();
+/// rows(3, &mut state);
+/// rows(1, &mut state);
+/// assert_eq!(state.expanded(0), Some(&true));
+/// assert_eq!(state.expanded(1), None);
+/// assert_eq!(format!("{state:?}"), r#"State { expanded: [true], text: ["row 0"] }"#);
+/// ```
+///
+/// ## Unmount
+/// Variables of a block are kept even if the block stops executing.
+/// `unmount` drops stack of the block if it was not entered during a call, like unmounting a component.
+/// The stack is dropped at the beginning of the call after a call not entering the block, even if it returned early.
+/// Give `unmount` option to the function to apply it on every block.
+/// ```
+/// # use retained::retained;
+/// #[retained(State)]
+/// fn view(show_details: bool) {
+///     if show_details {
+///         #[retained(unmount)]
+///         let ref mut details: String = String::from("details");
+///         println!("{details}");
+///     }
+/// }
+///
+/// #[retained(AppState, unmount)]
+/// fn app(page: u32) {
+///     match page {
+///         0 => {
+///             #[retained]
+///             let ref mut counter: u32 = 0;
+///             *counter += 1;
+///         }
+///         _ => {
+///             #[retained]
+///             let ref mut text: String = String::new();
+///             text.push('!');
+///         }
+///     }
 /// }
 /// ```
+///
+/// Effects of an unmounted block are cleaned up with its stack.
+/// ```
+/// # use retained::retained;
+/// # use std::sync::atomic::{AtomicU32, Ordering};
+/// static SUBSCRIPTIONS: AtomicU32 = AtomicU32::new(0);
+///
+/// #[retained(State, unmount)]
+/// fn feed(online: bool) {
+///     if !online {
+///         return;
+///     }
+///
+///     {
+///         retained::effect!((), || {
+///             SUBSCRIPTIONS.fetch_add(1, Ordering::Relaxed);
+///             || {
+///                 SUBSCRIPTIONS.fetch_sub(1, Ordering::Relaxed);
+///             }
+///         });
+///     }
+/// }
+///
+/// let mut state = State::new();
+/// feed(true, &mut state);
+/// assert_eq!(SUBSCRIPTIONS.load(Ordering::Relaxed), 1);
+///
+/// feed(false, &mut state);
+/// feed(false, &mut state);
+/// assert_eq!(SUBSCRIPTIONS.load(Ordering::Relaxed), 0);
+/// ```
+///
+/// ## Keyed
+/// A retained let inside of a loop shares single storage in every iteration.
+/// `key` mode stores the variable for each runtime key with `Hash + Eq` type instead.
+/// Variables of keys untouched during a call are dropped at the beginning of the next call.
+/// ```
+/// # use retained::retained;
+/// #[retained(State)]
+/// fn rows(ids: &[u32]) {
+///     for id in ids {
+///         #[retained(key: u32 = *id)]
+///         let ref mut clicks: u32 = 0;
+///         *clicks += 1;
+///     }
+/// }
+/// ```
+///
+/// The default storage requires `std` feature.
+/// Give `capacity` to use storage with fixed capacity instead, which panics when it is exceeded.
+/// ```
+/// # use retained::retained;
+/// #[retained(State)]
+/// fn rows(ids: &[u32]) {
+///     for id in ids {
+///         #[retained(key: u32 = *id, capacity = 16)]
+///         let ref mut clicks: u32 = 0;
+///         *clicks += 1;
+///     }
+/// }
+/// ```
+///
+/// ## Memo
+/// `memo` stores copies of dependencies next to the variable and runs the initializer again when any of them changes.
+/// Types of function arguments are inferred and other dependencies require a type like `items.len(): usize`.
+/// Dependencies are compared using `PartialEq` and copied using `ToOwned`, which requires `alloc` feature.
+/// ```
+/// # use retained::retained;
+/// #[retained(State)]
+/// fn greet(name: &str, count: u32) -> usize {
+///     #[retained(memo(name, count))]
+///     let ref greeting: String = format!("Hello {name}").repeat(count as usize);
+///
+///     #[retained(memo(greeting.len(): usize))]
+///     let ref mut changes: usize = 0;
+///     *changes += 1;
+///
+///     *changes
+/// }
+///
+/// let mut state = State::new();
+/// assert_eq!(greet("world", 1, &mut state), 1);
+/// assert_eq!(greet("world", 1, &mut state), 2);
+/// assert_eq!(greet("world", 2, &mut state), 1);
+/// ```
+///
+/// ## Effects
+/// `retained::effect!` statement stores cleanup of an effect like a retained let with `memo`.
+/// Setup runs again after previous cleanup when dependencies change,
+/// and cleanup also runs when the state or the stack of its block is dropped.
+/// The macro must be called through `retained::` path, as `effect!` macros of other crates are left alone.
+/// ```
+/// # use retained::retained;
+/// #[retained(State)]
+/// fn watch(path: &str) {
+///     retained::effect!((path), || {
+///         println!("watch {path}");
+///         move || println!("unwatch")
+///     });
+/// }
+/// ```
+///
+/// ## Calling retained functions
+/// `retained::call!` calls another `#[retained]` function, storing its state in a retained let of the caller.
+/// Arguments after the call are given to the constructor of the callee's state on first call.
+/// The callee must take its state as the last argument, and generic arguments of its state are given to the function path.
+/// Like retained lets, it cannot be used inside of arguments of other macros.
+///
+/// The state is found through a hidden type `__retained_callee_<function>` declared next to the function with the same visibility.
+/// It is part of the API of a public function, but it is not brought into scope by importing the function alone.
+/// The state type can be given before the call as `retained::call!(State => f(args))` instead.
+/// ```
+/// # use retained::retained;
+/// #[retained(State)]
+/// fn toolbar() {
+///     // Each call stores its own state
+///     retained::call!(button("Save"), 0);
+///     retained::call!(button("Load"), 0);
+///     retained::call!(ButtonState => button("Exit"), 0);
+/// }
+///
+/// #[retained(ButtonState(clicks: u32))]
+/// fn button(label: &str) {
+///     #[retained(default)]
+///     let ref mut clicks: u32 = clicks;
+///     *clicks += 1;
+///     println!("{label}: {clicks}");
+/// }
+/// ```
+///
+/// `marker` option declares a marker type with given name for a function taking the state as the last argument.
+/// It implements `retained::RetainedFn`, so the function can be called generically or stored with its state in `retained::WithState`.
+/// The marker can also be given to `retained::call!` in place of the state.
+///
+/// ## Async functions
+/// `async fn` can be retained too, and retained lets can be used across `.await`.
+/// The returned future borrows the state until it completes or is dropped.
+/// A value is stored only after its initializer completes,
+/// so if the future is dropped while initializing, the let is initialized again on next call.
+/// Blocks not reached by a dropped future are considered not entered during that call.
+/// `retained::call!` of an async function evaluates to its future, which borrows the caller's state.
+/// ```
+/// # use retained::retained;
+/// use std::{
+///     future::Future,
+///     pin::{pin, Pin},
+///     task::{Context, Poll, Waker},
+/// };
+///
+/// /// Future pending once before it is ready
+/// struct YieldNow(bool);
+///
+/// impl Future for YieldNow {
+///     type Output = ();
+///
+///     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+///         if self.0 {
+///             return Poll::Ready(());
+///         }
+///
+///         self.0 = true;
+///         cx.waker().wake_by_ref();
+///         Poll::Pending
+///     }
+/// }
+///
+/// /// Poll the future until it is ready
+/// fn block_on<F: Future>(future: F) -> F::Output {
+///     let mut future = pin!(future);
+///     let mut cx = Context::from_waker(Waker::noop());
+///     loop {
+///         if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+///             return output;
+///         }
+///     }
+/// }
+///
+/// #[retained(State)]
+/// async fn load(id: u32) -> u32 {
+///     #[retained]
+///     let ref mut loads: u32 = 0;
+///     *loads += 1;
+///
+///     #[retained]
+///     let ref mut data: u32 = {
+///         YieldNow(false).await;
+///         id * 10
+///     };
+///     YieldNow(false).await;
+///
+///     *data + *loads
+/// }
+///
+/// let mut state = State::new();
+///
+/// // Dropped while initializing `data`
+/// {
+///     let mut future = pin!(load(1, &mut state));
+///     let mut cx = Context::from_waker(Waker::noop());
+///     assert!(future.as_mut().poll(&mut cx).is_pending());
+/// }
+///
+/// assert_eq!(block_on(load(2, &mut state)), 22);
+/// assert_eq!(block_on(load(3, &mut state)), 23);
+/// ```
+///
+/// ## Reset
+/// `reset` rebuilds the whole state taking same arguments as `new`.
+/// `reset_inplace` drops lazily initialized variables, so their initializers run again on next call.
+/// ```
+/// # use retained::retained;
+/// #[retained(State(start: u32))]
+/// fn count() -> u32 {
+///     #[retained(default)]
+///     let ref mut count: u32 = start;
+///     *count += 1;
+///
+///     *count
+/// }
+///
+/// let mut state = State::new(10);
+/// assert_eq!(count(&mut state), 11);
+///
+/// state.reset(0);
+/// assert_eq!(count(&mut state), 1);
+/// ```
+///
+/// ## Accessors
+/// `accessors` option generates getters and `_mut` methods on the state named after binding of each retained let.
+/// Lazily initialized variables return `None` until initialized.
+/// Accessors of variables in `per_iteration` blocks take index of each iteration, and keyed ones take the key.
+/// ```
+/// # use retained::retained;
+/// #[retained(State, accessors)]
+/// fn zoom(ids: &[u32]) {
+///     #[retained]
+///     let ref mut zoom_factor: f32 = 1.0;
+///
+///     for id in ids {
+///         #[retained(key: u32 = *id)]
+///         let ref mut clicks: u32 = 0;
+///         *clicks += 1;
+///     }
+/// }
+///
+/// let mut state = State::new();
+/// assert_eq!(state.zoom_factor(), None);
+///
+/// zoom(&[1], &mut state);
+/// *state.zoom_factor_mut().unwrap() = 2.0;
+/// assert_eq!(state.zoom_factor(), Some(&2.0));
+/// assert_eq!(state.clicks(&1), Some(&1));
+/// ```
+///
+/// Retained lets cannot be named after methods of the state, such as `new`, `reset`, `reset_inplace` or `restore`.
+/// ```compile_fail
+/// # use retained::retained;
+/// #[retained(State, accessors)]
+/// fn search() {
+///     #[retained]
+///     let ref mut reset: bool = false;
+/// }
+/// ```
+///
+/// Names of `_mut` accessors are checked too, so a retained let cannot be named after the `_mut` accessor of another one.
+/// ```compile_fail
+/// # use retained::retained;
+/// #[retained(State, accessors)]
+/// fn search() {
+///     #[retained]
+///     let ref mut query: String = String::new();
+///
+///     #[retained]
+///     let ref mut query_mut: bool = false;
+/// }
+/// ```
+///
+/// ## Debug
+/// `Debug` implementation of the state prints nothing by default.
+/// `debug` option prints each retained let under its binding name.
+/// Uninitialized variables are printed as `<uninit>` and ones not implementing `Debug` as `<opaque>`.
+/// Variables in `per_iteration` blocks are printed as a list of each iteration.
+/// ```
+/// # use retained::retained;
+/// #[retained(State, debug)]
+/// fn draw(show: bool) {
+///     #[retained(default)]
+///     let ref mut frames: u32 = 0;
+///     *frames += 1;
+///
+///     if show {
+///         #[retained]
+///         let ref mut title: String = String::from("title");
+///     }
+/// }
+///
+/// let mut state = State::new();
+/// draw(false, &mut state);
+/// assert_eq!(format!("{state:?}"), "State { frames: 1, title: <uninit> }");
+/// ```
+///
+/// ## Derive
+/// `derive` option applies given derives to the state struct and its inner struct.
+/// `Debug` cannot be derived as it is always implemented.
+/// ```
+/// # use retained::retained;
+/// #[retained(State, derive(Clone, PartialEq))]
+/// fn count() -> u32 {
+///     #[retained]
+///     let ref mut count: u32 = 0;
+///     *count += 1;
+///
+///     *count
+/// }
+///
+/// let mut state = State::new();
+/// count(&mut state);
+///
+/// let mut fork = state.clone();
+/// assert!(fork == state);
+/// assert_eq!(count(&mut fork), 2);
+/// assert!(fork != state);
+/// ```
+///
+/// Keyed storages compare and hash their entries regardless of order, with or without `capacity`, so states with keyed lets can derive `PartialEq` and `Hash`.
+/// States storing `effect!` cannot derive `Clone`, `Copy`, `PartialEq`, `Eq`, `PartialOrd`, `Ord` or `Hash`,
+/// since effects cannot be cloned or compared.
+/// ```
+/// # use retained::retained;
+/// # use std::{collections::HashSet, hash::{BuildHasher, RandomState}};
+/// #[retained(State, derive(Clone, PartialEq, Eq, Hash))]
+/// fn visits(pages: &[&str]) {
+///     for page in pages {
+///         #[retained(key: String = page.to_string())]
+///         let ref mut count: u32 = 0;
+///         *count += 1;
+///     }
+/// }
+///
+/// let mut state = State::new();
+/// visits(&["home", "about", "docs"], &mut state);
+/// let mut reordered = State::new();
+/// visits(&["docs", "home", "about"], &mut reordered);
+///
+/// let hasher = RandomState::new();
+/// assert!(state == reordered);
+/// assert_eq!(hasher.hash_one(&state), hasher.hash_one(&reordered));
+///
+/// let mut seen = HashSet::new();
+/// seen.insert(state.clone());
+/// assert!(seen.contains(&reordered));
+///
+/// #[retained(FixedState, derive(PartialEq, Eq, Hash))]
+/// fn fixed_visits(pages: &[u32]) {
+///     for page in pages {
+///         #[retained(key: u32 = *page, capacity = 4)]
+///         let ref mut count: u32 = 0;
+///         *count += 1;
+///     }
+/// }
+///
+/// let mut state = FixedState::new();
+/// fixed_visits(&[1, 2], &mut state);
+/// let mut reordered = FixedState::new();
+/// fixed_visits(&[2, 1], &mut reordered);
+///
+/// assert!(state == reordered);
+/// assert_eq!(hasher.hash_one(&state), hasher.hash_one(&reordered));
+/// ```
+///
+/// ## Serde
+/// `serde` option implements `Serialize` for the state and generates `restore` method overwriting retained lets with deserialized ones.
+/// States without constructor arguments also implement `Deserialize`.
+/// Requires `serde` feature.
+///
+/// Each retained let is serialized under its binding name, and uninitialized variables are absent.
+/// Variables in `per_iteration` blocks, memoized and unnamed ones are not serialized.
+/// States of functions called with `retained::call!` are not serialized either, so they start over after deserialization.
+/// `skip_serde` option excludes a retained let, keeping its value on `restore`.
+/// `id` option gives a stable identifier to serialize under instead of binding name, so the variable can be renamed.
+/// ```
+/// # use retained::retained;
+/// #[retained(State, serde, accessors)]
+/// fn form(show_details: bool) {
+///     #[retained]
+///     let ref mut name: String = String::new();
+///
+///     #[retained(skip_serde)]
+///     let ref mut focused: bool = false;
+///
+///     #[retained(default, id = "zoom")]
+///     let ref mut zoom_factor: f32 = 1.0;
+///
+///     if show_details {
+///         #[retained]
+///         let ref mut details: String = String::from("details");
+///     }
+///
+///     *focused = true;
+/// }
+///
+/// let mut state = State::new();
+/// form(false, &mut state);
+/// assert_eq!(serde_json::to_string(&state).unwrap(), r#"{"zoom":1.0,"name":""}"#);
+///
+/// form(true, &mut state);
+/// let json = serde_json::to_string(&state).unwrap();
+/// assert_eq!(json, r#"{"zoom":1.0,"name":"","details":"details"}"#);
+///
+/// // Skipped variables are initialized again
+/// let restored: State = serde_json::from_str(&json).unwrap();
+/// assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+/// assert_eq!(restored.focused(), None);
+/// ```
+///
+/// Deserialization ignores unknown fields, and variables missing in the input keep their values on `restore`,
+/// so a deserialized state runs initializers of missing variables.
+/// Saved states keep loading after retained lets are added, removed or reordered, while a value of a changed type is an error.
+/// Values are deserialized directly, so formats that are not self-describing work too.
+/// ```
+/// # use retained::retained;
+/// #[retained(State, serde, accessors)]
+/// fn settings() {
+///     #[retained(default)]
+///     let ref mut volume: u32 = 10;
+///
+///     #[retained]
+///     let ref mut theme: String = String::from("light");
+/// }
+///
+/// let saved = r#"{"theme":"dark","removed":true}"#;
+/// let state: State = serde_json::from_str(saved).unwrap();
+/// assert_eq!(*state.volume(), 10);
+/// assert_eq!(state.theme().map(String::as_str), Some("dark"));
+///
+/// let mut state = State::new();
+/// settings(&mut state);
+/// *state.volume_mut() = 5;
+/// state.restore(&mut serde_json::Deserializer::from_str(saved)).unwrap();
+/// assert_eq!(*state.volume(), 5);
+/// assert_eq!(state.theme().map(String::as_str), Some("dark"));
+///
+/// assert!(serde_json::from_str::<State>(r#"{"volume":"loud"}"#).is_err());
+/// ```
+///
+/// Keyed lets are serialized as maps from keys to values, and keys of any type are restored.
+/// ```
+/// # use retained::retained;
+/// #[retained(State, serde, accessors)]
+/// fn counters(ids: &[u32]) {
+///     for id in ids {
+///         #[retained(key: u32 = *id)]
+///         let ref mut count: u32 = 0;
+///         *count += 1;
+///     }
+/// }
+///
+/// let mut state = State::new();
+/// counters(&[7], &mut state);
+/// counters(&[7], &mut state);
+///
+/// let saved = serde_json::to_string(&state).unwrap();
+/// assert_eq!(saved, r#"{"count":{"7":2}}"#);
+/// let state: State = serde_json::from_str(&saved).unwrap();
+/// assert_eq!(state.count(&7), Some(&2));
+/// assert_eq!(state.count(&1), None);
+/// ```
+///
+/// ## Reflection
+/// Every state implements `retained::RetainedState`, listing named retained lets and visiting their values.
+/// States stored in retained lets are visited recursively, so tools can walk any state without knowing its type.
+///
+/// ## State argument
+/// By default, the state argument is appended to the end of arguments and cannot be accessed in the function body.
+/// `position` option places the argument at `first`, `last`, given index, `before(arg)` or `after(arg)`.
+/// `name` option binds the whole state argument to the given identifier.
+/// ```
+/// # use retained::retained;
+/// #[retained(State, position = first, name = state)]
+/// fn count(step: u32) -> u32 {
+///     #[retained]
+///     let ref mut count: u32 = 0;
+///     *count += step;
+///     let count = *count;
+///
+///     println!("{state:?}");
+///     count
+/// }
+///
+/// let mut state = State::new();
+/// assert_eq!(count(&mut state, 2), 2);
+/// assert_eq!(count(&mut state, 2), 4);
+/// ```
+///
+/// ## Returning references
+/// Signature can refer to `'state` lifetime, which is the lifetime of the state argument.
+/// It is declared on the function if it is not already, so references into retained lets can be returned.
+/// ```
+/// # use retained::retained;
+/// #[retained(State)]
+/// fn rows(count: usize) -> &'state mut Vec<usize> {
+///     #[retained]
+///     let ref mut rows: Vec<usize> = Vec::new();
+///     rows.resize(count, 0);
+///     rows
+/// }
+///
+/// let mut state = State::new();
+/// rows(2, &mut state)[1] = 5;
+/// assert_eq!(rows(3, &mut state), &[0, 5, 0]);
+/// ```
+///
+/// `'state` is not available with `from` option, since the state is not an argument.
+///
+/// ## Methods
+/// State struct cannot be declared inside impl block.
+/// Put `#[retained]` without arguments on the impl block to use the macro on its methods.
+/// State structs are declared next to the impl block and methods get additional `&mut State` argument.
+/// ```
+/// # use retained::retained;
+/// struct Counter {
+///     step: u32,
+/// }
+///
+/// #[retained]
+/// impl Counter {
+///     #[retained(CounterState)]
+///     pub fn count(&self) -> u32 {
+///         #[retained]
+///         let ref mut count: u32 = 0;
+///         *count += self.step;
+///
+///         *count
+///     }
+/// }
+///
+/// let counter = Counter { step: 2 };
+/// let mut state = CounterState::new();
+/// assert_eq!(counter.count(&mut state), 2);
+/// assert_eq!(counter.count(&mut state), 4);
+/// ```
+///
+/// Since state structs are declared outside of impl block, types of retained variables cannot refer to `Self`.
+///
+/// ## Shared state
+/// Functions in an impl block or an inline module with `#[retained]` can declare the same state,
+/// which is combined into one struct containing retained lets of every function.
+/// The state can be declared only by its name after it is declared with generics or constructor once.
+/// Options of the state are combined, and its visibility is taken from the first function.
+/// ```
+/// #[retained::retained]
+/// mod panel {
+///     #[retained(Panel(title: &'static str))]
+///     pub fn header() {
+///         #[retained(default)]
+///         let ref title: &'static str = title;
+///         println!("{title}");
+///     }
+///
+///     #[retained(Panel)]
+///     pub fn body() {
+///         #[retained]
+///         let ref mut clicks: u32 = 0;
+///         *clicks += 1;
+///     }
+/// }
+///
+/// let mut state = panel::Panel::new("panel");
+/// panel::header(&mut state);
+/// panel::body(&mut state);
+/// ```
+///
+/// ## Borrowing state
+/// Signature of the function is kept unchanged with `from` option.
+/// It takes a place expression, like a field of `self`, and the state is borrowed from it instead of additional argument.
+/// This is useful for functions with fixed signature like trait methods.
+/// ```
+/// # use retained::retained;
+/// trait Widget {
+///     fn draw(&mut self) -> u32;
+/// }
+///
+/// struct Counter {
+///     state: CounterState,
+/// }
+///
+/// #[retained]
+/// impl Widget for Counter {
+///     #[retained(CounterState, from = self.state)]
+///     fn draw(&mut self) -> u32 {
+///         #[retained]
+///         let ref mut count: u32 = 0;
+///         *count += 1;
+///
+///         *count
+///     }
+/// }
+///
+/// let mut counter = Counter {
+///     state: CounterState::new(),
+/// };
+/// assert_eq!(counter.draw(), 1);
+/// assert_eq!(counter.draw(), 2);
+/// ```
 #[proc_macro_attribute]
 pub fn retained(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let mut f = parse_macro_input!(item as ItemFn);
+    match parse_macro_input!(item as Item) {
+        Item::Fn(mut f) => {
+            let attr = parse_macro_input!(attr as RetainedAttr);
+            if let Some(receiver) = f.sig.receiver() {
+                return syn::Error::new_spanned(
+                    receiver,
+                    "retained method requires `#[retained]` on its impl block",
+                )
+                .to_compile_error()
+                .into();
+            }
+
+            let callable = attr.callable();
+            let marker = attr.marker.clone();
+            let mut state = State::new(&f.vis, &attr);
+            if let Err(err) = expand(attr, &mut state, &mut f.sig, &mut f.block, true) {
+                return err.to_compile_error().into();
+            }
+            let marker = callable.then(|| state.markers(&f.vis, &f.sig, marker.as_ref()));
+            TokenStream::from(quote_spanned! { Span::mixed_site() =>
+                #state
+                #marker
+                #f
+            })
+        }
+
+        Item::Impl(item) => {
+            if !attr.is_empty() {
+                return syn::Error::new(
+                    Span::call_site(),
+                    "retained on impl block does not take arguments",
+                )
+                .to_compile_error()
+                .into();
+            }
+
+            TokenStream::from(expand_impl(item))
+        }
+
+        Item::Mod(item) => {
+            if !attr.is_empty() {
+                return syn::Error::new(
+                    Span::call_site(),
+                    "retained on module does not take arguments",
+                )
+                .to_compile_error()
+                .into();
+            }
+
+            TokenStream::from(expand_mod(item))
+        }
+
+        item => syn::Error::new_spanned(item, "expected function, impl block or module")
+            .to_compile_error()
+            .into(),
+    }
+}
 
-    let mut state = State {
-        vis: f.vis.clone(),
-        decl: parse_macro_input!(attr as StateDecl),
-        fields: Vec::new(),
+/// Create a closure owning the storage of its retained lets.
+///
+/// Body of the closure is expanded like a `#[retained]` function,
+/// and its state is created with the closure and moved into it, so the closure implements `FnMut`.
+/// The closure always captures by move, and it can be stored as `Box<dyn FnMut(..)>`.
+/// Since the state is declared inside of an expression, types of retained lets cannot refer to generic parameters of the enclosing function.
+/// ```
+/// # use retained::retained;
+/// let mut counter: Box<dyn FnMut(u32) -> u32> = Box::new(retained::closure!(|step: u32| {
+///     #[retained]
+///     let ref mut count: u32 = 0;
+///     *count += step;
+///     *count
+/// }));
+///
+/// assert_eq!(counter(1), 1);
+/// assert_eq!(counter(2), 3);
+/// ```
+#[proc_macro]
+pub fn closure(input: TokenStream) -> TokenStream {
+    let closure = parse_macro_input!(input as ExprClosure);
+    match expand_closure(closure) {
+        Ok(tokens) => TokenStream::from(tokens),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Expand retained lets of the function into the state.
+/// Generics of the function used by the state are lifted if `lift` is set and the state declares none.
+fn expand(
+    attr: RetainedAttr,
+    state: &mut State,
+    sig: &mut Signature,
+    block: &mut Block,
+    lift: bool,
+) -> syn::Result<()> {
+    state.owned = state.fields.len();
+
+    let inner = Ident::new("__inner", Span::mixed_site());
+    let state_arg: Expr = match attr.name {
+        Some(ref name) => parse_quote!(#name.0),
+        None => parse_quote!(#inner),
     };
+    let args = typed_args(sig);
+    RetainedLetExpander::expand(state_arg.clone(), &args, state, block, attr.unmount);
+    if lift && attr.decl.generics.params.is_empty() {
+        state.lift_generics(&sig.generics);
+    }
+    block.stmts.splice(0..0, state.prologue(&state_arg));
+
+    match attr.from {
+        Some(from) => {
+            let state_ty = &state.decl.name;
+            block.stmts.insert(
+                0,
+                parse_quote_spanned! { Span::mixed_site() =>
+                    let #state_ty (#inner) = &mut #from;
+                },
+            );
+        }
 
-    let name = Ident::new("__inner", Span::mixed_site());
-    RetainedLetExpander::expand(name.clone(), 0, &mut state, &mut f.block);
+        None => {
+            let index = match attr.position {
+                Some(ref position) => position.index(sig)?,
+                None => sig.inputs.len(),
+            };
 
-    let state_arg = StateArg {
-        name,
-        decl: &state.decl,
+            let state_arg = StateArg {
+                named: attr.name.is_some(),
+                name: attr.name.unwrap_or(inner),
+                decl: &state.decl,
+                lifetime: state_lifetime(sig),
+            };
+            sig.inputs.insert(index, parse_quote!(#state_arg));
+        }
+    }
+
+    Ok(())
+}
+
+/// `'state` lifetime of the state argument if the signature refers to it,
+/// declared on the function unless it is already
+fn state_lifetime(sig: &mut Signature) -> Option<Lifetime> {
+    struct StateLifetimeVisitor {
+        found: bool,
+    }
+
+    impl Visit<'_> for StateLifetimeVisitor {
+        fn visit_lifetime(&mut self, i: &Lifetime) {
+            self.found |= i.ident == "state";
+        }
+    }
+
+    let mut visitor = StateLifetimeVisitor { found: false };
+    visitor.visit_signature(sig);
+    if !visitor.found {
+        return None;
+    }
+
+    let lifetime = Lifetime::new("'state", Span::call_site());
+    if !sig
+        .generics
+        .lifetimes()
+        .any(|param| param.lifetime == lifetime)
+    {
+        sig.generics.params.insert(
+            0,
+            GenericParam::Lifetime(LifetimeParam::new(lifetime.clone())),
+        );
+    }
+
+    Some(lifetime)
+}
+
+/// Function in an impl block or module sharing states with others
+struct SharedFn<'a> {
+    attrs: &'a mut Vec<Attribute>,
+    vis: &'a Visibility,
+    sig: &'a mut Signature,
+    block: &'a mut Block,
+}
+
+/// Expand retained functions, combining states declared with the same name into one.
+/// Marker types of the functions are generated if they are not methods.
+fn expand_shared<'a>(
+    fns: impl IntoIterator<Item = SharedFn<'a>>,
+    markers: bool,
+) -> proc_macro2::TokenStream {
+    let mut errors = Vec::new();
+
+    let mut retained_fns = Vec::new();
+    for f in fns {
+        let Some(index) = f.attrs.iter().position(is_retained_attr) else {
+            continue;
+        };
+
+        match f.attrs.remove(index).parse_args::<RetainedAttr>() {
+            Ok(attr) => retained_fns.push((f, attr)),
+            Err(err) => errors.push(err.to_compile_error()),
+        }
+    }
+
+    // Functions may declare a shared state only by its name
+    let mut decls = Vec::<&StateDecl>::new();
+    for (_, attr) in &retained_fns {
+        if attr.decl.is_bare() {
+            continue;
+        }
+
+        match decls.iter().find(|decl| decl.name == attr.decl.name) {
+            Some(decl) if !decl.same(&attr.decl) => errors.push(
+                syn::Error::new_spanned(
+                    &attr.decl.name,
+                    format!(
+                        "state `{}` is declared differently by another function, declare it the same or only by its name",
+                        attr.decl.name
+                    ),
+                )
+                .to_compile_error(),
+            ),
+            Some(_) => {}
+            None => decls.push(&attr.decl),
+        }
+    }
+    let decls = decls.into_iter().cloned().collect::<Vec<_>>();
+
+    // Generics are lifted only from the function using the state alone
+    let shared = retained_fns
+        .iter()
+        .enumerate()
+        .filter(|(index, (_, attr))| {
+            retained_fns[..*index]
+                .iter()
+                .any(|(_, other)| other.decl.name == attr.decl.name)
+        })
+        .map(|(_, (_, attr))| attr.decl.name.clone())
+        .collect::<Vec<_>>();
+
+    let mut states = Vec::<State>::new();
+    let mut callables = Vec::new();
+    for (f, mut attr) in retained_fns {
+        let lift = !shared.contains(&attr.decl.name);
+        if let Some(decl) = decls.iter().find(|decl| decl.name == attr.decl.name) {
+            attr.decl = decl.clone();
+        }
+
+        let index = match states
+            .iter()
+            .position(|state| state.decl.name == attr.decl.name)
+        {
+            Some(index) => {
+                states[index].merge(&attr);
+                index
+            }
+            None => {
+                states.push(State::new(f.vis, &attr));
+                states.len() - 1
+            }
+        };
+
+        let callable = markers && attr.callable();
+        let marker = attr.marker.clone();
+        match marker {
+            Some(ref marker) if !markers => errors.push(
+                syn::Error::new_spanned(marker, "`marker` cannot be used on methods")
+                    .to_compile_error(),
+            ),
+            _ => {}
+        }
+
+        match expand(attr, &mut states[index], f.sig, f.block, lift) {
+            Ok(()) if callable => callables.push((f.vis, f.sig.clone(), marker, index)),
+            Ok(()) => {}
+            Err(err) => errors.push(err.to_compile_error()),
+        }
+    }
+
+    let markers = callables
+        .iter()
+        .map(|(vis, sig, marker, index)| states[*index].markers(vis, sig, marker.as_ref()));
+    quote! {
+        #(#errors)*
+        #(#states)*
+        #(#markers)*
+    }
+}
+
+fn expand_impl(mut item: ItemImpl) -> proc_macro2::TokenStream {
+    let fns = item
+        .items
+        .iter_mut()
+        .filter_map(|impl_item| match impl_item {
+            ImplItem::Fn(f) => Some(SharedFn {
+                attrs: &mut f.attrs,
+                vis: &f.vis,
+                sig: &mut f.sig,
+                block: &mut f.block,
+            }),
+            _ => None,
+        });
+    let states = expand_shared(fns, false);
+
+    quote! {
+        #states
+        #item
+    }
+}
+
+fn expand_mod(mut item: ItemMod) -> proc_macro2::TokenStream {
+    let Some((_, ref mut items)) = item.content else {
+        return syn::Error::new_spanned(item, "retained on module requires its content inline")
+            .to_compile_error();
+    };
+
+    let fns = items.iter_mut().filter_map(|item| match item {
+        Item::Fn(f) => Some(SharedFn {
+            attrs: &mut f.attrs,
+            vis: &f.vis,
+            sig: &mut f.sig,
+            block: &mut f.block,
+        }),
+        _ => None,
+    });
+    let states = expand_shared(fns, true);
+    items.push(Item::Verbatim(states));
+
+    item.into_token_stream()
+}
+
+fn expand_closure(mut closure: ExprClosure) -> syn::Result<proc_macro2::TokenStream> {
+    let name = Ident::new("ClosureState", Span::mixed_site());
+    let local = Ident::new("__state", Span::mixed_site());
+    let attr = RetainedAttr {
+        decl: StateDecl {
+            name: name.clone(),
+            generics: Generics::default(),
+            constructor: Punctuated::new(),
+        },
+        from: Some(parse_quote!(#local)),
+        position: None,
+        name: None,
+        unmount: false,
+        accessors: false,
+        debug: false,
+        derives: Punctuated::new(),
+        serde: false,
+        marker: None,
     };
-    f.sig.inputs.push(parse_quote!(#state_arg));
 
-    TokenStream::from(quote_spanned! { Span::mixed_site() =>
-        #state
-        #f
+    // Typed arguments of the closure are used to infer types of memo dependencies
+    let inputs = closure.inputs.iter().filter_map(|pat| match pat {
+        Pat::Type(pat) => Some(FnArg::Typed(pat.clone())),
+        _ => None,
+    });
+    let mut sig: Signature = parse_quote!(fn __closure(#(#inputs),*));
+    let mut block = match *closure.body {
+        Expr::Block(ExprBlock {
+            ref attrs,
+            label: None,
+            ref block,
+        }) if attrs.is_empty() => block.clone(),
+        ref body => parse_quote!({ #body }),
+    };
+
+    let mut state = State::new(&Visibility::Inherited, &attr);
+    expand(attr, &mut state, &mut sig, &mut block, false)?;
+    *closure.body = Expr::Block(ExprBlock {
+        attrs: Vec::new(),
+        label: None,
+        block,
+    });
+    closure.capture = Some(Default::default());
+
+    Ok(quote_spanned! { Span::mixed_site() =>
+        {
+            #state
+            let mut #local = #name::new();
+            #closure
+        }
     })
 }
+
+fn is_retained_attr(attr: &Attribute) -> bool {
+    let mut segments = attr.path().segments.iter().map(|segment| &segment.ident);
+    match (segments.next(), segments.next(), segments.next()) {
+        (Some(name), None, None) => name == "retained",
+        (Some(krate), Some(name), None) => krate == "retained" && name == "retained",
+        _ => false,
+    }
+}
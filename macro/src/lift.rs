@@ -0,0 +1,139 @@
+use syn::{
+    visit::{self, Visit},
+    GenericParam, Generics, Ident, Lifetime, Path, WhereClause, WherePredicate,
+};
+
+use crate::state::State;
+
+impl State {
+    /// Declare generic parameters of the function used by types of retained lets or constructor arguments,
+    /// with bounds and where predicates referring to them
+    pub fn lift_generics(&mut self, generics: &Generics) {
+        let mut used = UsedParams::new(generics);
+        for field in &self.fields {
+            used.visit_type(&field.ty);
+        }
+        for arg in &self.decl.constructor {
+            used.visit_type(&arg.ty);
+        }
+
+        let predicates = generics
+            .where_clause
+            .iter()
+            .flat_map(|clause| &clause.predicates)
+            .collect::<Vec<_>>();
+        let mut lifted = vec![false; predicates.len()];
+
+        // Bounds of used parameters can refer to other parameters
+        loop {
+            let count = used.count();
+            for (param, _) in generics
+                .params
+                .iter()
+                .zip(used.used.clone())
+                .filter(|(_, used)| *used)
+            {
+                used.visit_generic_param(param);
+            }
+
+            for (predicate, lifted) in predicates.iter().zip(&mut lifted) {
+                if !*lifted && used.mentioned_by(predicate) {
+                    *lifted = true;
+                    used.visit_where_predicate(predicate);
+                }
+            }
+
+            if used.count() == count {
+                break;
+            }
+        }
+
+        if used.count() == 0 {
+            return;
+        }
+
+        let state_generics = &mut self.decl.generics;
+        state_generics.params = generics
+            .params
+            .iter()
+            .zip(&used.used)
+            .filter(|(_, used)| **used)
+            .map(|(param, _)| param.clone())
+            .collect();
+
+        let predicates = predicates
+            .into_iter()
+            .zip(lifted)
+            .filter(|(_, lifted)| *lifted)
+            .map(|(predicate, _)| WherePredicate::clone(predicate));
+        match state_generics.where_clause {
+            Some(ref mut clause) => clause.predicates.extend(predicates),
+            None => {
+                let mut predicates = predicates.peekable();
+                if predicates.peek().is_some() {
+                    state_generics.where_clause = Some(WhereClause {
+                        where_token: Default::default(),
+                        predicates: predicates.collect(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Generic parameters of the function referred by visited syntax
+pub struct UsedParams<'a> {
+    generics: &'a Generics,
+    used: Vec<bool>,
+}
+
+impl<'a> UsedParams<'a> {
+    pub fn new(generics: &'a Generics) -> Self {
+        Self {
+            generics,
+            used: vec![false; generics.params.len()],
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.used.iter().filter(|used| **used).count()
+    }
+
+    /// Predicate refers to any of used parameters
+    fn mentioned_by(&self, predicate: &WherePredicate) -> bool {
+        let mut mentioned = UsedParams::new(self.generics);
+        mentioned.visit_where_predicate(predicate);
+
+        mentioned
+            .used
+            .iter()
+            .zip(&self.used)
+            .any(|(mentioned, used)| *mentioned && *used)
+    }
+
+    fn mark(&mut self, found: impl Fn(&GenericParam) -> bool) {
+        for (param, used) in self.generics.params.iter().zip(&mut self.used) {
+            *used |= found(param);
+        }
+    }
+}
+
+impl Visit<'_> for UsedParams<'_> {
+    fn visit_path(&mut self, i: &Path) {
+        // `T`, `T::Assoc` or const parameter `N`
+        if let (None, Some(first)) = (&i.leading_colon, i.segments.first()) {
+            let ident: &Ident = &first.ident;
+            self.mark(|param| match param {
+                GenericParam::Type(param) => param.ident == *ident,
+                GenericParam::Const(param) => param.ident == *ident,
+                GenericParam::Lifetime(_) => false,
+            });
+        }
+
+        visit::visit_path(self, i);
+    }
+
+    fn visit_lifetime(&mut self, i: &Lifetime) {
+        self.mark(|param| matches!(param, GenericParam::Lifetime(param) if param.lifetime == *i));
+    }
+}
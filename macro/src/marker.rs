@@ -0,0 +1,207 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
+use syn::{
+    parse_quote,
+    visit::{self, Visit},
+    visit_mut::{self, VisitMut},
+    FnArg, GenericParam, Generics, Ident, Lifetime, LifetimeParam, ParenthesizedGenericArguments,
+    ReturnType, Signature, Type, TypeBareFn, TypeImplTrait, TypeReference, Visibility,
+};
+
+use crate::{lift::UsedParams, state::State};
+
+impl State {
+    /// Hidden type named after the function, so `call!` can find the state of a callee from its path,
+    /// and the marker type given by `marker` option implementing `RetainedFn`.
+    /// `sig` is the expanded signature taking the state as the last argument.
+    pub fn markers(
+        &self,
+        vis: &Visibility,
+        sig: &Signature,
+        marker: Option<&Ident>,
+    ) -> TokenStream {
+        let f = &sig.ident;
+        let callee = self.marker_struct(
+            vis,
+            &callee_ident(f),
+            quote!(#[doc(hidden)] #[allow(non_camel_case_types)]),
+        );
+
+        let Some(marker) = marker else {
+            return callee;
+        };
+
+        let doc = format!("Marker type of retained function [`{f}`], implementing `RetainedFn`");
+        let marker_struct = self.marker_struct(vis, marker, quote!(#[doc = #doc]));
+        let name = &self.decl.name;
+        let (_, ty_gen, _) = self.decl.generics.split_for_impl();
+        let retained_fn = retained_fn(
+            f,
+            &parse_quote!(#name #ty_gen),
+            &parse_quote!(#marker #ty_gen),
+            sig,
+        );
+
+        quote!(
+            #callee
+            #marker_struct
+            #retained_fn
+        )
+    }
+
+    /// Type taking generic parameters of the state, implementing `Callee`
+    fn marker_struct(&self, vis: &Visibility, ident: &Ident, attrs: TokenStream) -> TokenStream {
+        let name = &self.decl.name;
+        let generics = &self.decl.generics;
+
+        // Bounds are not required by the marker
+        let params = generics.params.iter().map(|param| match param {
+            GenericParam::Lifetime(param) => param.lifetime.to_token_stream(),
+            GenericParam::Type(param) => {
+                let ident = &param.ident;
+                let default = param.default.as_ref().map(|ty| quote!(= #ty));
+                quote!(#ident #default)
+            }
+            GenericParam::Const(param) => {
+                let ident = &param.ident;
+                let ty = &param.ty;
+                let default = param.default.as_ref().map(|expr| quote!(= #expr));
+                quote!(const #ident: #ty #default)
+            }
+        });
+        let lifetimes = generics.lifetimes().map(|param| &param.lifetime);
+        let types = generics.type_params().map(|param| &param.ident);
+        let (impl_gen, ty_gen, where_gen) = generics.split_for_impl();
+
+        quote_spanned!(Span::mixed_site() =>
+            #attrs
+            #vis struct #ident <#(#params),*> {
+                _marker: ::core::marker::PhantomData<fn() -> (#(&#lifetimes (),)* #(#types,)*)>,
+            }
+
+            impl #impl_gen ::retained::__private::Callee for #ident #ty_gen #where_gen {
+                type State = #name #ty_gen;
+            }
+        )
+    }
+}
+
+/// Name of hidden type finding the state of the function, which cannot collide with user items
+pub fn callee_ident(f: &Ident) -> Ident {
+    format_ident!("__retained_callee_{}", f, span = f.span())
+}
+
+/// `RetainedFn` implementation calling the function, if its signature can be named
+fn retained_fn(f: &Ident, state: &Type, marker: &Type, sig: &Signature) -> Option<TokenStream> {
+    if sig.asyncness.is_some() || sig.unsafety.is_some() || sig.variadic.is_some() {
+        return None;
+    }
+
+    let mut impl_trait = ImplTraitVisitor(false);
+    impl_trait.visit_signature(sig);
+    if impl_trait.0 {
+        return None;
+    }
+
+    let mut generics = sig.generics.clone();
+    let mut names = ElidedLifetimeNamer::new(&mut generics);
+    let mut tys = Vec::new();
+    for arg in sig.inputs.iter().take(sig.inputs.len() - 1) {
+        let FnArg::Typed(arg) = arg else {
+            return None;
+        };
+
+        let mut ty = arg.ty.as_ref().clone();
+        names.visit_type_mut(&mut ty);
+        tys.push(ty);
+    }
+    let output = match sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ref ty) => ty.to_token_stream(),
+    };
+
+    // Parameters of the implementation must be constrained by the arguments or the marker
+    let mut used = UsedParams::new(&generics);
+    for ty in &tys {
+        used.visit_type(ty);
+    }
+    used.visit_type(marker);
+    if used.count() != generics.params.len() {
+        return None;
+    }
+
+    let args = (0..tys.len())
+        .map(|index| format_ident!("__arg{}", index, span = Span::mixed_site()))
+        .collect::<Vec<_>>();
+    let (impl_gen, _, where_gen) = generics.split_for_impl();
+
+    Some(quote_spanned!(Span::mixed_site() =>
+        impl #impl_gen ::retained::RetainedFn<(#(#tys,)*)> for #marker #where_gen {
+            type State = #state;
+
+            type Output = #output;
+
+            fn call(
+                __state: &mut Self::State,
+                (#(#args,)*): (#(#tys,)*),
+            ) -> Self::Output {
+                #f(#(#args,)* __state)
+            }
+        }
+    ))
+}
+
+/// Names elided lifetimes of argument types, as they cannot be elided in trait arguments of an implementation
+struct ElidedLifetimeNamer<'a> {
+    generics: &'a mut Generics,
+    count: usize,
+}
+
+impl<'a> ElidedLifetimeNamer<'a> {
+    fn new(generics: &'a mut Generics) -> Self {
+        Self { generics, count: 0 }
+    }
+
+    fn name(&mut self, span: Span) -> Lifetime {
+        let lifetime = Lifetime::new(&format!("'__retained{}", self.count), span);
+        self.count += 1;
+        self.generics.params.insert(
+            0,
+            GenericParam::Lifetime(LifetimeParam::new(lifetime.clone())),
+        );
+
+        lifetime
+    }
+}
+
+impl VisitMut for ElidedLifetimeNamer<'_> {
+    fn visit_type_reference_mut(&mut self, i: &mut TypeReference) {
+        if i.lifetime.is_none() {
+            i.lifetime = Some(self.name(i.and_token.span));
+        }
+
+        visit_mut::visit_type_reference_mut(self, i);
+    }
+
+    fn visit_lifetime_mut(&mut self, i: &mut Lifetime) {
+        if i.ident == "_" {
+            *i = self.name(i.span());
+        }
+    }
+
+    // Lifetimes of function pointers and `Fn` traits are higher-ranked
+    fn visit_type_bare_fn_mut(&mut self, _: &mut TypeBareFn) {}
+
+    fn visit_parenthesized_generic_arguments_mut(&mut self, _: &mut ParenthesizedGenericArguments) {
+    }
+}
+
+/// Finds `impl Trait` types
+struct ImplTraitVisitor(bool);
+
+impl Visit<'_> for ImplTraitVisitor {
+    fn visit_type_impl_trait(&mut self, i: &TypeImplTrait) {
+        self.0 = true;
+        visit::visit_type_impl_trait(self, i);
+    }
+}
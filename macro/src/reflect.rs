@@ -0,0 +1,310 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote_spanned};
+use syn::{visit::Visit, GenericParam, Generics, Ident, Index, Lifetime, Type, TypePath};
+
+use crate::{
+    retained_let::Binding,
+    state::{FieldKind, State},
+};
+
+/// Named retained let visited by reflection
+struct ReflectField {
+    name: String,
+    ty: Type,
+    /// Initialized on access rather than with the state
+    lazy: bool,
+    /// Statements visiting each value
+    visit: TokenStream,
+    visit_mut: TokenStream,
+}
+
+impl State {
+    fn reflect_fields(&self) -> Vec<ReflectField> {
+        let mut fields = Vec::new();
+
+        for (index, field) in self.fields.iter().enumerate() {
+            let index = Index::from(index);
+            let info = fields.len();
+            match field.kind {
+                FieldKind::Default(Some(Binding { ref name, .. })) => {
+                    let single = quote_spanned!(Span::mixed_site() => ::retained::Slot::Single);
+                    let visit = visit_value(
+                        info,
+                        &single,
+                        quote_spanned!(Span::mixed_site() =>
+                            ::core::option::Option::Some(&self.0. #index)
+                        ),
+                    );
+                    let visit_mut = visit_value_mut(
+                        info,
+                        &single,
+                        quote_spanned!(Span::mixed_site() =>
+                            ::core::option::Option::Some(&mut self.0. #index)
+                        ),
+                    );
+
+                    fields.push(ReflectField {
+                        name: name.to_string(),
+                        ty: field.ty.clone(),
+                        lazy: false,
+                        visit: quote_spanned!(Span::mixed_site() => #visit;),
+                        visit_mut: quote_spanned!(Span::mixed_site() => _ = #visit_mut;),
+                    });
+                }
+
+                FieldKind::Keyed {
+                    name: Some(Binding { ref name, .. }),
+                    ref value,
+                    ..
+                } => {
+                    let key = quote_spanned!(Span::mixed_site() =>
+                        ::retained::Slot::Key(&::retained::__private::debug_fn(|__f| {
+                            (&::retained::__private::DebugWrap(__key)).retained_fmt(__f)
+                        }))
+                    );
+                    let entry = quote_spanned!(Span::mixed_site() =>
+                        ::core::option::Option::Some(__value)
+                    );
+                    let visit = visit_value(info, &key, entry.clone());
+                    let visit_mut = visit_value_mut(info, &key, entry);
+
+                    fields.push(ReflectField {
+                        name: name.to_string(),
+                        ty: Type::clone(value),
+                        lazy: true,
+                        visit: quote_spanned!(Span::mixed_site() =>
+                            for (__key, __value) in self.0. #index .iter() {
+                                #visit;
+                            }
+                        ),
+                        visit_mut: quote_spanned!(Span::mixed_site() =>
+                            self.0. #index .retain(|__key, __value| {
+                                #visit_mut != ::retained::Visit::Reset
+                            });
+                        ),
+                    });
+                }
+
+                FieldKind::Inplace(ref stack) => {
+                    let mut accessors = Vec::new();
+                    stack.accessors(&mut Vec::new(), &mut accessors);
+                    for accessor in accessors {
+                        let info = fields.len();
+                        let memo = accessor.memo();
+                        let visit = accessor.visit(
+                            quote_spanned!(Span::mixed_site() => self.0. #index),
+                            false,
+                            |entry, indices| {
+                                let value = if memo {
+                                    quote_spanned!(Span::mixed_site() =>
+                                        #entry .as_ref().map(|__entry| &__entry.0)
+                                    )
+                                } else {
+                                    quote_spanned!(Span::mixed_site() => #entry .as_ref())
+                                };
+                                let visit = visit_value(info, &iterations(indices), value);
+                                quote_spanned!(Span::mixed_site() => #visit;)
+                            },
+                        );
+                        let visit_mut = accessor.visit(
+                            quote_spanned!(Span::mixed_site() => self.0. #index),
+                            true,
+                            |entry, indices| {
+                                let value = if memo {
+                                    quote_spanned!(Span::mixed_site() =>
+                                        #entry .as_mut().map(|__entry| &mut __entry.0)
+                                    )
+                                } else {
+                                    quote_spanned!(Span::mixed_site() => #entry .as_mut())
+                                };
+                                let visit = visit_value_mut(info, &iterations(indices), value);
+                                quote_spanned!(Span::mixed_site() =>
+                                    if #visit == ::retained::Visit::Reset {
+                                        #entry = ::core::option::Option::None;
+                                    }
+                                )
+                            },
+                        );
+
+                        fields.push(ReflectField {
+                            name: accessor.binding.name.to_string(),
+                            ty: accessor.ty,
+                            lazy: true,
+                            visit,
+                            visit_mut,
+                        });
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        fields
+    }
+
+    /// `RetainedState` implementation
+    pub fn reflect_impl(&self) -> TokenStream {
+        let name = &self.decl.name;
+        let (impl_gen, ty_gen, where_gen) = self.decl.generics.split_for_impl();
+        let fields = self.reflect_fields();
+
+        let infos = fields.iter().map(|field| {
+            let name = &field.name;
+            let ty = &field.ty;
+            let lazy = field.lazy;
+            quote_spanned!(Span::mixed_site() =>
+                ::retained::FieldInfo {
+                    name: #name,
+                    type_name: ::core::stringify!(#ty),
+                    lazy: #lazy,
+                }
+            )
+        });
+        let vtables = fields
+            .iter()
+            .map(|field| vtable(&field.ty, &self.decl.generics))
+            .collect::<Vec<_>>();
+        let vtable_locals = (0..fields.len()).map(vtable_local).collect::<Vec<_>>();
+        let visits = fields.iter().map(|field| &field.visit);
+        let visits_mut = fields.iter().map(|field| &field.visit_mut);
+        let imports = quote_spanned!(Span::mixed_site() =>
+            #[allow(unused_imports)]
+            use ::retained::__private::{
+                ProbeAny as _, ProbeAnyMut as _, ProbeDebug as _, ProbeName as _, ProbeNoAny as _,
+                ProbeNoAnyMut as _, ProbeNoDebug as _, ProbeNoName as _, ProbeNoVisit as _,
+                ProbeNoVisitMut as _, ProbeVisit as _, ProbeVisitMut as _, ViaDebug as _,
+                ViaOpaque as _,
+            };
+        );
+
+        quote_spanned!(Span::mixed_site() =>
+            impl #impl_gen ::retained::RetainedState for #name #ty_gen #where_gen {
+                const NAME: &'static str = ::core::stringify!(#name);
+
+                const FIELD_COUNT: usize = <Self as ::retained::RetainedState>::FIELDS.len();
+
+                const FIELDS: &'static [::retained::FieldInfo] = &[#(#infos),*];
+
+                fn visit(&self, __visitor: &mut dyn ::retained::StateVisitor) {
+                    #imports
+                    #(let #vtable_locals = #vtables;)*
+                    #(#visits)*
+                }
+
+                fn visit_mut(&mut self, __visitor: &mut dyn ::retained::StateVisitorMut) {
+                    #imports
+                    #(let #vtable_locals = #vtables;)*
+                    #(#visits_mut)*
+                }
+            }
+        )
+    }
+}
+
+/// Functions of the type chosen by probing its capabilities
+fn vtable(ty: &Type, generics: &Generics) -> TokenStream {
+    let probe = quote_spanned!(Span::mixed_site() =>
+        (&::retained::__private::Probe::<#ty>::new())
+    );
+
+    // `'static` bound is not considered while probing, so it would fail to compile
+    let (any, any_mut) = if GenericsVisitor::mentions(ty, generics) {
+        (
+            quote_spanned!(Span::mixed_site() => |_| ::core::option::Option::None),
+            quote_spanned!(Span::mixed_site() => |_| ::core::option::Option::None),
+        )
+    } else {
+        (
+            quote_spanned!(Span::mixed_site() => #probe .retained_any()),
+            quote_spanned!(Span::mixed_site() => #probe .retained_any_mut()),
+        )
+    };
+
+    quote_spanned!(Span::mixed_site() =>
+        ::retained::__private::VTable::<#ty> {
+            name: #probe .retained_name(),
+            debug: #probe .retained_debug(),
+            any: #any,
+            any_mut: #any_mut,
+            visit: #probe .retained_visit(),
+            visit_mut: #probe .retained_visit_mut(),
+        }
+    )
+}
+
+/// Slot of a value in iterations of given indices
+fn iterations(indices: &[Ident]) -> TokenStream {
+    if indices.is_empty() {
+        quote_spanned!(Span::mixed_site() => ::retained::Slot::Single)
+    } else {
+        quote_spanned!(Span::mixed_site() => ::retained::Slot::Iterations(&[#(#indices),*]))
+    }
+}
+
+fn vtable_local(field: usize) -> Ident {
+    format_ident!("__vtable{}", field, span = Span::mixed_site())
+}
+
+fn visit_value(field: usize, slot: &TokenStream, value: TokenStream) -> TokenStream {
+    let vtable = vtable_local(field);
+    quote_spanned!(Span::mixed_site() =>
+        ::retained::__private::visit_value(
+            __visitor,
+            &<Self as ::retained::RetainedState>::FIELDS[#field],
+            #slot,
+            #value,
+            #vtable,
+        )
+    )
+}
+
+fn visit_value_mut(field: usize, slot: &TokenStream, value: TokenStream) -> TokenStream {
+    let vtable = vtable_local(field);
+    quote_spanned!(Span::mixed_site() =>
+        ::retained::__private::visit_value_mut(
+            __visitor,
+            &<Self as ::retained::RetainedState>::FIELDS[#field],
+            #slot,
+            #value,
+            #vtable,
+        )
+    )
+}
+
+/// Finds generic parameters of the state or non-`'static` lifetimes in a type
+struct GenericsVisitor<'a> {
+    generics: &'a Generics,
+    found: bool,
+}
+
+impl GenericsVisitor<'_> {
+    fn mentions(ty: &Type, generics: &Generics) -> bool {
+        let mut this = GenericsVisitor {
+            generics,
+            found: false,
+        };
+        this.visit_type(ty);
+
+        this.found
+    }
+}
+
+impl Visit<'_> for GenericsVisitor<'_> {
+    fn visit_type_path(&mut self, i: &TypePath) {
+        // `T` or `T::Assoc`
+        if let (None, None, Some(first)) =
+            (&i.qself, &i.path.leading_colon, i.path.segments.first())
+        {
+            self.found |= self.generics.params.iter().any(
+                |param| matches!(param, GenericParam::Type(param) if param.ident == first.ident),
+            );
+        }
+
+        syn::visit::visit_type_path(self, i);
+    }
+
+    fn visit_lifetime(&mut self, i: &Lifetime) {
+        self.found |= i.ident != "static";
+    }
+}
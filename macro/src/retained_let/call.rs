@@ -0,0 +1,117 @@
+use proc_macro2::Span;
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_quote_spanned,
+    punctuated::Punctuated,
+    Attribute, Expr, ExprCall, ExprPath, Ident, Macro, Token, Type, TypePath,
+};
+
+use super::is_retained_macro;
+use crate::marker::callee_ident;
+
+const CALLEE_ATTR: &str = "__retained_callee";
+
+/// Expression `call!(f(args), constructor args)` or `call!(State => f(args), constructor args)`
+pub struct CallExpr {
+    /// State of the callee, or a marker type finding it
+    pub state: Option<Type>,
+    pub call: ExprCall,
+    pub constructor: Punctuated<Expr, Token![,]>,
+}
+
+impl Parse for CallExpr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let fork = input.fork();
+        let state = match fork.parse::<Type>() {
+            Ok(state) if fork.peek(Token![=>]) => {
+                input.parse::<Type>()?;
+                input.parse::<Token![=>]>()?;
+                Some(state)
+            }
+            _ => None,
+        };
+
+        let call = match input.parse()? {
+            Expr::Call(call) if matches!(*call.func, Expr::Path(_)) => call,
+            expr => {
+                return Err(syn::Error::new_spanned(
+                    expr,
+                    "expected call of a function declared with `#[retained]` like `f(args)`",
+                ))
+            }
+        };
+
+        let constructor = if input.is_empty() {
+            Punctuated::new()
+        } else {
+            input.parse::<Token![,]>()?;
+            Punctuated::parse_terminated(input)?
+        };
+
+        Ok(Self {
+            state,
+            call,
+            constructor,
+        })
+    }
+}
+
+impl CallExpr {
+    pub fn try_from_macro(mac: &Macro) -> Option<syn::Result<Self>> {
+        if !is_retained_macro(mac, "call") {
+            return None;
+        }
+
+        Some(mac.parse_body())
+    }
+
+    /// Marker of the retained let storing state of the callee
+    pub fn is_callee_attr(attr: &Attribute) -> bool {
+        attr.path().is_ident(CALLEE_ATTR)
+    }
+
+    /// Block storing state of the callee in a retained let and passing it as the last argument
+    pub fn low(self) -> Expr {
+        let Self {
+            state,
+            call: ExprCall { func, args, .. },
+            constructor,
+        } = self;
+        let Expr::Path(ExprPath {
+            ref qself,
+            ref path,
+            ..
+        }) = *func
+        else {
+            unreachable!()
+        };
+
+        // Without the state, hidden type named after the function finds it
+        let marker = state.unwrap_or_else(|| {
+            let mut path = path.clone();
+            let segment = path.segments.last_mut().unwrap();
+            segment.ident = callee_ident(&segment.ident);
+
+            Type::Path(TypePath {
+                qself: qself.clone(),
+                path,
+            })
+        });
+        let ty: Type = parse_quote_spanned!(Span::mixed_site() =>
+            <#marker as ::retained::__private::Callee>::State
+        );
+        let name = &path.segments.last().unwrap().ident;
+        let local = Ident::new(&name.to_string(), Span::mixed_site());
+        let args = args.iter();
+        let callee = Ident::new(CALLEE_ATTR, Span::mixed_site());
+
+        parse_quote_spanned!(Span::mixed_site() =>
+            {
+                #[retained(skip_serde)]
+                #[#callee]
+                let ref mut #local: #ty = <#ty>::new(#constructor);
+                #func(#(#args,)* #local)
+            }
+        )
+    }
+}
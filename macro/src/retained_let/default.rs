@@ -1,39 +1,43 @@
 use proc_macro2::Span;
 use quote::quote_spanned;
-use syn::{Expr, Ident, Index, Local, Pat, Stmt, Type};
+use syn::{Expr, Index, Local, Pat, Stmt, Type};
 
-use crate::state::{State, StateField};
+use crate::state::{FieldKind, State, StateField};
 
-use super::{extract_init, LocalTyVisitor};
+use super::{extract_init, Binding, LocalTyVisitor, SerdeOptions};
 
 pub struct DefaultLetStmt {
     pub pat: Pat,
     pub ty: Type,
     pub init: Expr,
+    pub serde: SerdeOptions,
 }
 
 impl DefaultLetStmt {
-    pub fn try_from(local: &Local) -> syn::Result<Self> {
+    pub fn try_from(local: &Local, serde: SerdeOptions) -> syn::Result<Self> {
         let Some(ty) = LocalTyVisitor::find(local) else {
             return Err(syn::Error::new_spanned(
                 local,
                 "missing type for retained let",
             ));
         };
-        let init = extract_init(&local)?;
+        let init = extract_init(local)?;
+        serde.check(&local.pat)?;
 
         Ok(Self {
             pat: local.pat.clone(),
             ty,
             init,
+            serde,
         })
     }
 
-    pub fn low(self, state_arg: &Ident, state: &mut State) -> Stmt {
+    pub fn low(self, state_arg: &Expr, state: &mut State) -> Stmt {
         let index = Index::from(state.fields.len());
         state.fields.push(StateField {
             ty: self.ty,
             init: self.init,
+            kind: FieldKind::Default(Binding::find(&self.pat, &self.serde)),
         });
 
         let pat = &self.pat;
@@ -0,0 +1,60 @@
+use proc_macro2::Span;
+use quote::quote_spanned;
+use syn::{
+    parse::{Parse, ParseStream},
+    Expr, Ident, Macro, Stmt, Token,
+};
+
+use super::{
+    is_retained_macro,
+    memo::Deps,
+    stack::{BlockStack, StackEntry},
+};
+
+/// Statement `effect!((deps), setup)`
+pub struct EffectStmt {
+    pub deps: Deps,
+    pub setup: Expr,
+}
+
+impl Parse for EffectStmt {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let deps = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let setup = input.parse()?;
+        if !input.is_empty() {
+            input.parse::<Token![,]>()?;
+        }
+
+        Ok(Self { deps, setup })
+    }
+}
+
+impl EffectStmt {
+    pub fn try_from_macro(mac: &Macro) -> Option<syn::Result<Self>> {
+        if !is_retained_macro(mac, "effect") {
+            return None;
+        }
+
+        Some(mac.parse_body())
+    }
+
+    pub fn low(self, block_state: &Ident, stack: &mut BlockStack) -> Stmt {
+        let Self { deps, setup } = self;
+        stack.entries.push(StackEntry::Effect(Box::new(deps.ty())));
+
+        let slot = Ident::new("_effect", Span::mixed_site());
+        let entry = deps.low_entry(
+            block_state,
+            &slot,
+            quote_spanned!(Span::mixed_site() =>
+                if let ::core::option::Option::Some((__effect, _)) = #slot {
+                    __effect.cleanup();
+                }
+
+                ::retained::__private::Effect::setup(#setup)
+            ),
+        );
+        Stmt::Expr(Expr::Verbatim(entry), Some(Default::default()))
+    }
+}
@@ -1,49 +1,138 @@
 use proc_macro2::Span;
 use quote::quote_spanned;
-use syn::{Expr, Ident, Local, Pat, Stmt, Type};
+use syn::{
+    parse::{Parse, ParseStream},
+    Expr, Ident, Local, Pat, Stmt, Token, Type,
+};
 
-use super::{extract_init, LocalTyVisitor};
+use super::{
+    call::CallExpr,
+    extract_init, kw,
+    memo::Deps,
+    stack::{low_entry, BlockStack, StackEntry},
+    Binding, LocalTyVisitor, SerdeOptions,
+};
+
+#[derive(Default)]
+pub struct InplaceOptions {
+    pub per_iteration: bool,
+    pub unmount: bool,
+    /// Recompute the value when dependencies change
+    pub memo: Option<Deps>,
+    pub serde: SerdeOptions,
+}
+
+impl Parse for InplaceOptions {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut options = Self::default();
+        if input.peek(kw::inplace) {
+            input.parse::<kw::inplace>()?;
+        } else {
+            options.parse_option(input)?;
+        }
+
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+
+            options.parse_option(input)?;
+        }
+
+        Ok(options)
+    }
+}
+
+impl InplaceOptions {
+    fn parse_option(&mut self, input: ParseStream) -> syn::Result<()> {
+        let lookahead = input.lookahead1();
+        if lookahead.peek(kw::per_iteration) {
+            input.parse::<kw::per_iteration>()?;
+            self.per_iteration = true;
+            Ok(())
+        } else if lookahead.peek(kw::unmount) {
+            input.parse::<kw::unmount>()?;
+            self.unmount = true;
+            Ok(())
+        } else if SerdeOptions::peek(&lookahead) {
+            self.serde.parse_option(input)
+        } else if lookahead.peek(kw::memo) {
+            let memo = input.parse::<kw::memo>()?;
+            if self.memo.is_some() {
+                return Err(syn::Error::new_spanned(memo, "duplicate option `memo`"));
+            }
+
+            self.memo = Some(input.parse()?);
+            Ok(())
+        } else {
+            Err(lookahead.error())
+        }
+    }
+}
 
 pub struct InplaceLetStmt {
     pub pat: Pat,
     pub ty: Type,
     pub init: Expr,
+    pub options: InplaceOptions,
+    /// Stores state of a function called with `call!`
+    pub callee: bool,
 }
 
 impl InplaceLetStmt {
-    pub fn try_from(local: &Local) -> syn::Result<Self> {
+    pub fn try_from(local: &Local, options: InplaceOptions) -> syn::Result<Self> {
         let Some(ty) = LocalTyVisitor::find(local) else {
             return Err(syn::Error::new_spanned(
                 local,
                 "missing type for retained let",
             ));
         };
-        let init = extract_init(&local)?;
+        let init = extract_init(local)?;
+        options.serde.check(&local.pat)?;
 
         Ok(Self {
             pat: local.pat.clone(),
             ty,
             init,
+            options,
+            callee: local.attrs.iter().any(CallExpr::is_callee_attr),
         })
     }
 
-    pub fn low(self, block_state: &Ident, stack: &mut Vec<Type>) -> Stmt {
-        let Self { pat, ty, init } = self;
-        stack.push(ty);
+    pub fn low(self, block_state: &Ident, stack: &mut BlockStack) -> Stmt {
+        let Self {
+            pat,
+            ty,
+            init,
+            options,
+            callee,
+        } = self;
+        stack.per_iteration |= options.per_iteration;
+        stack.unmount |= options.unmount;
 
+        let name = Binding::find(&pat, &options.serde).map(|binding| Binding { callee, ..binding });
+        let tmp = Ident::new("__tmp", Span::mixed_site());
+        let Some(deps) = options.memo else {
+            stack.entries.push(StackEntry::Let(ty, name));
+            let entry = low_entry(block_state, &tmp, init);
+            return Stmt::Expr(
+                Expr::Verbatim(quote_spanned!(Span::mixed_site() =>
+                    #entry
+                    let #pat = *#tmp;
+                )),
+                Some(Default::default()),
+            );
+        };
+
+        stack
+            .entries
+            .push(StackEntry::Memo(ty, Box::new(deps.ty()), name));
+        let entry = deps.low_entry(block_state, &tmp, init);
         Stmt::Expr(
             Expr::Verbatim(quote_spanned!(Span::mixed_site() =>
-                let (ref mut __tmp, ref mut #block_state) = {
-                    if #block_state.is_none() {
-                        * #block_state = ::core::option::Option::Some(({
-                            #init
-                        }, Default::default()));
-                    }
-
-                    #block_state .as_mut().unwrap()
-                };
-
-                let #pat = *__tmp;
+                #entry
+                let #pat = #tmp.0;
             )),
             Some(Default::default()),
         )
@@ -0,0 +1,136 @@
+use proc_macro2::Span;
+use quote::quote_spanned;
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_quote, Expr, Index, LitInt, Local, Pat, Stmt, Token, Type,
+};
+
+use crate::state::{FieldKind, State, StateField};
+
+use super::{extract_init, kw, Binding, LocalTyVisitor, SerdeOptions};
+
+/// `key: Type = expr` with optional `capacity = N` and serialization options
+pub struct KeyedOptions {
+    pub ty: Type,
+    pub key: Expr,
+    pub capacity: Option<LitInt>,
+    pub serde: SerdeOptions,
+}
+
+impl Parse for KeyedOptions {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<kw::key>()?;
+        if !input.peek(Token![:]) {
+            return Err(input.error("expected key type like `key: u64 = id`"));
+        }
+        input.parse::<Token![:]>()?;
+        let ty = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let key = input.parse()?;
+
+        let mut capacity = None;
+        let mut serde = SerdeOptions::default();
+        while input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+
+            let lookahead = input.lookahead1();
+            if lookahead.peek(kw::capacity) && capacity.is_none() {
+                input.parse::<kw::capacity>()?;
+                input.parse::<Token![=]>()?;
+                capacity = Some(input.parse()?);
+            } else if SerdeOptions::peek(&lookahead) {
+                serde.parse_option(input)?;
+            } else {
+                return Err(lookahead.error());
+            }
+        }
+
+        Ok(Self {
+            ty,
+            key,
+            capacity,
+            serde,
+        })
+    }
+}
+
+pub struct KeyedLetStmt {
+    pub pat: Pat,
+    pub ty: Type,
+    pub init: Expr,
+    pub options: KeyedOptions,
+}
+
+impl KeyedLetStmt {
+    pub fn try_from(local: &Local, options: KeyedOptions) -> syn::Result<Self> {
+        let Some(ty) = LocalTyVisitor::find(local) else {
+            return Err(syn::Error::new_spanned(
+                local,
+                "missing type for retained let",
+            ));
+        };
+        let init = extract_init(local)?;
+        options.serde.check(&local.pat)?;
+
+        Ok(Self {
+            pat: local.pat.clone(),
+            ty,
+            init,
+            options,
+        })
+    }
+
+    pub fn low(self, state_arg: &Expr, state: &mut State) -> Stmt {
+        let Self {
+            pat,
+            ty,
+            init,
+            options:
+                KeyedOptions {
+                    ty: key_ty,
+                    key,
+                    capacity,
+                    serde,
+                },
+        } = self;
+
+        let index = Index::from(state.fields.len());
+        state.fields.push(StateField {
+            ty: match capacity {
+                Some(capacity) => parse_quote!(
+                    ::retained::__private::FixedKeyed<#key_ty, #ty, #capacity>
+                ),
+                None => parse_quote!(::retained::__private::Keyed<#key_ty, #ty>),
+            },
+            init: parse_quote!(::core::default::Default::default()),
+            kind: FieldKind::Keyed {
+                name: Binding::find(&pat, &serde),
+                key: Box::new(key_ty.clone()),
+                value: Box::new(ty),
+            },
+        });
+
+        Stmt::Expr(
+            Expr::Verbatim(quote_spanned!(Span::mixed_site() =>
+                let __tmp = {
+                    let __key: #key_ty = #key;
+                    if #state_arg . #index .touch(&__key) {
+                        #state_arg . #index .get_mut(&__key).unwrap()
+                    } else {
+                        let __value = {
+                            #init
+                        };
+
+                        #state_arg . #index .insert(__key, __value)
+                    }
+                };
+
+                let #pat = *__tmp;
+            )),
+            Some(Default::default()),
+        )
+    }
+}
@@ -0,0 +1,177 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote_spanned, ToTokens};
+
+use super::stack::split_entry;
+use syn::{
+    parenthesized,
+    parse::{Parse, ParseStream},
+    parse_quote,
+    punctuated::Punctuated,
+    token::Comma,
+    Expr, FnArg, Ident, Index, Pat, Signature, Token, Type, TypeTuple,
+};
+
+/// Dependency of memoized let like `a` or `a.len(): usize`
+pub struct Dep {
+    pub expr: Expr,
+    /// Borrowed type of the dependency. Inferred from function argument if omitted.
+    pub ty: Option<Type>,
+}
+
+impl Parse for Dep {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let expr = input.parse()?;
+        let ty = if input.peek(Token![:]) {
+            input.parse::<Token![:]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+
+        Ok(Self { expr, ty })
+    }
+}
+
+/// Parenthesized dependencies
+pub struct Deps(pub Punctuated<Dep, Comma>);
+
+impl Parse for Deps {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let content;
+        _ = parenthesized!(content in input);
+        Ok(Self(Punctuated::parse_terminated(&content)?))
+    }
+}
+
+impl Deps {
+    /// Infer omitted types from arguments of the function
+    pub fn resolve(&mut self, args: &[(Ident, Type)]) -> syn::Result<()> {
+        for dep in &mut self.0 {
+            if dep.ty.is_some() {
+                continue;
+            }
+
+            let arg = match dep.expr {
+                Expr::Path(ref path) => path.path.get_ident(),
+                _ => None,
+            };
+            let Some((_, ty)) = arg.and_then(|arg| args.iter().find(|(name, _)| name == arg))
+            else {
+                return Err(syn::Error::new_spanned(
+                    &dep.expr,
+                    "cannot infer type of dependency, give it like `a: u32`",
+                ));
+            };
+
+            dep.ty = Some(match ty {
+                Type::Reference(reference) => Type::clone(&reference.elem),
+                ty => ty.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Type of stored copies
+    pub fn ty(&self) -> Type {
+        Type::Tuple(TypeTuple {
+            paren_token: Default::default(),
+            elems: self
+                .0
+                .iter()
+                .map(|dep| -> Type {
+                    let ty = dep.ty.as_ref().unwrap();
+                    parse_quote!(<#ty as ::retained::__private::ToOwned>::Owned)
+                })
+                .collect(),
+        })
+    }
+
+    /// Evaluate dependencies and bind `__changed` to whether they differ from copies in `stored`,
+    /// which is an `Option` of stored copies.
+    /// Evaluated dependencies can be copied with [`Deps::copy`] afterwards.
+    fn check(&self, stored: TokenStream) -> TokenStream {
+        let bindings = self.bindings();
+        let evals = self.0.iter().zip(&bindings).map(|(dep, binding)| {
+            let Dep { expr, ty } = dep;
+            quote_spanned!(Span::mixed_site() => let #binding: &#ty = &(#expr);)
+        });
+        let unchanged = self
+            .0
+            .iter()
+            .zip(&bindings)
+            .enumerate()
+            .map(|(index, (dep, binding))| {
+                let index = Index::from(index);
+                let ty = &dep.ty;
+                quote_spanned!(Span::mixed_site() =>
+                    && ::core::cmp::PartialEq::eq(
+                        #binding,
+                        ::core::borrow::Borrow::<#ty>::borrow(&__deps. #index),
+                    )
+                )
+            });
+
+        quote_spanned!(Span::mixed_site() =>
+            #(#evals)*
+            let __changed = match #stored {
+                ::core::option::Option::Some(__deps) => !(true #(#unchanged)*),
+                ::core::option::Option::None => true,
+            };
+        )
+    }
+
+    /// Owned copies of evaluated dependencies to store
+    fn copy(&self) -> TokenStream {
+        let bindings = self.bindings();
+
+        quote_spanned!(Span::mixed_site() =>
+            (#(::retained::__private::ToOwned::to_owned(#bindings),)*)
+        )
+    }
+
+    /// Initialize next entry of the stack with copies of dependencies if they changed and bind it.
+    /// `init` is evaluated while `binding` holds the previous entry.
+    pub fn low_entry(
+        &self,
+        block_state: &Ident,
+        binding: &Ident,
+        init: impl ToTokens,
+    ) -> TokenStream {
+        let split = split_entry(block_state, binding);
+        let check = self.check(quote_spanned!(Span::mixed_site() =>
+            #binding .as_ref().map(|__entry| &__entry.1)
+        ));
+        let copy = self.copy();
+
+        quote_spanned!(Span::mixed_site() =>
+            #split
+            #check
+            if __changed {
+                let __value = ({ #init }, #copy);
+                * #binding = ::core::option::Option::Some(__value);
+            }
+            let #binding = #binding.as_mut().unwrap();
+        )
+    }
+
+    fn bindings(&self) -> Vec<Ident> {
+        (0..self.0.len())
+            .map(|index| format_ident!("__dep{}", index, span = Span::mixed_site()))
+            .collect()
+    }
+}
+
+/// Arguments of the function bound to identifiers with their types
+pub fn typed_args(sig: &Signature) -> Vec<(Ident, Type)> {
+    sig.inputs
+        .iter()
+        .filter_map(|arg| match arg {
+            FnArg::Typed(arg) => match *arg.pat {
+                Pat::Ident(ref pat) => Some((pat.ident.clone(), Type::clone(&arg.ty))),
+                _ => None,
+            },
+            FnArg::Receiver(_) => None,
+        })
+        .collect()
+}
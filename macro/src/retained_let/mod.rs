@@ -1,40 +1,83 @@
+mod call;
 mod default;
+mod effect;
 mod inplace;
+mod keyed;
+mod memo;
+mod stack;
 
+pub use call::CallExpr;
 pub use default::DefaultLetStmt;
-pub use inplace::InplaceLetStmt;
+pub use effect::EffectStmt;
+pub use inplace::{InplaceLetStmt, InplaceOptions};
+pub use keyed::{KeyedLetStmt, KeyedOptions};
+pub use memo::typed_args;
+pub use stack::{BlockStack, StackEntry};
 
 use proc_macro2::Span;
-use quote::{format_ident, quote_spanned};
+use quote::{format_ident, quote_spanned, ToTokens};
 use syn::{
-    parse::{Parse, ParseStream},
+    parse::{Lookahead1, Parse, ParseStream},
     parse_quote,
     visit::Visit,
-    visit_mut::VisitMut,
-    AttrStyle, Attribute, Block, Expr, Ident, Index, Local, LocalInit, Meta, PatType, Stmt, Type,
-    TypeTuple,
+    visit_mut::{self, VisitMut},
+    AttrStyle, Attribute, Block, Expr, Ident, Index, Item, Lifetime, LitStr, Local, LocalInit,
+    Macro, Meta, ParenthesizedGenericArguments, Pat, PatType, Stmt, Token, Type, TypeBareFn,
+    TypeReference,
 };
 
-use crate::state::{State, StateField};
+use crate::state::{FieldKind, State, StateField};
+
+mod kw {
+    syn::custom_keyword!(inplace);
+    syn::custom_keyword!(default);
+    syn::custom_keyword!(key);
+    syn::custom_keyword!(capacity);
+    syn::custom_keyword!(per_iteration);
+    syn::custom_keyword!(unmount);
+    syn::custom_keyword!(memo);
+    syn::custom_keyword!(skip_serde);
+    syn::custom_keyword!(id);
+}
 
 enum InitMode {
-    Inplace,
-    Default,
+    Inplace(InplaceOptions),
+    Default(SerdeOptions),
+    Keyed(Box<KeyedOptions>),
 }
 
 impl Parse for InitMode {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        input.step(|cursor| {
-            if let Some((ident, rest)) = cursor.ident() {
-                if ident == "inplace" {
-                    return Ok((Self::Inplace, rest));
-                } else if ident == "default" {
-                    return Ok((Self::Default, rest));
+        let lookahead = input.lookahead1();
+        if lookahead.peek(kw::default) {
+            input.parse::<kw::default>()?;
+            let mut serde = SerdeOptions::default();
+            while !input.is_empty() {
+                input.parse::<Token![,]>()?;
+                if input.is_empty() {
+                    break;
+                }
+
+                let lookahead = input.lookahead1();
+                if !SerdeOptions::peek(&lookahead) {
+                    return Err(lookahead.error());
                 }
+                serde.parse_option(input)?;
             }
 
-            Err(cursor.error("expected `inplace` or `default`"))
-        })
+            Ok(Self::Default(serde))
+        } else if lookahead.peek(kw::key) {
+            Ok(Self::Keyed(Box::new(input.parse()?)))
+        } else if lookahead.peek(kw::inplace)
+            || lookahead.peek(kw::per_iteration)
+            || lookahead.peek(kw::unmount)
+            || lookahead.peek(kw::memo)
+            || SerdeOptions::peek(&lookahead)
+        {
+            Ok(Self::Inplace(input.parse()?))
+        } else {
+            Err(lookahead.error())
+        }
     }
 }
 
@@ -45,7 +88,7 @@ impl InitMode {
         }
 
         let Meta::List(ref list) = attr.meta else {
-            return Some(Ok(InitMode::Inplace));
+            return Some(Ok(InitMode::Inplace(InplaceOptions::default())));
         };
 
         Some(match list.parse_args::<InitMode>() {
@@ -58,6 +101,7 @@ impl InitMode {
 pub enum RetainedLetStmt {
     Inplace(InplaceLetStmt),
     Default(DefaultLetStmt),
+    Keyed(Box<KeyedLetStmt>),
 }
 
 impl RetainedLetStmt {
@@ -76,105 +120,291 @@ impl RetainedLetStmt {
     }
 
     fn try_from_local_inner(i: &Local, init: InitMode) -> syn::Result<Self> {
-        Ok(match init {
-            InitMode::Inplace => Self::Inplace(InplaceLetStmt::try_from(i)?),
-            InitMode::Default => Self::Default(DefaultLetStmt::try_from(i)?),
-        })
+        let this = match init {
+            InitMode::Inplace(options) => Self::Inplace(InplaceLetStmt::try_from(i, options)?),
+            InitMode::Default(serde) => Self::Default(DefaultLetStmt::try_from(i, serde)?),
+            InitMode::Keyed(options) => Self::Keyed(Box::new(KeyedLetStmt::try_from(i, *options)?)),
+        };
+
+        let tys = match this {
+            Self::Inplace(ref inplace) => vec![&inplace.ty],
+            Self::Default(ref default) => vec![&default.ty],
+            Self::Keyed(ref keyed) => vec![&keyed.options.ty, &keyed.ty],
+        };
+        for ty in tys {
+            ElidedLifetimeVisitor::check(ty)?;
+        }
+
+        Ok(this)
     }
 }
 
 pub struct RetainedLetExpander<'a> {
-    state_arg: Ident,
+    state_arg: Expr,
+    /// Typed arguments of the function
+    args: &'a [(Ident, Type)],
     block_state: Ident,
+    children: Ident,
     depth: usize,
     state: &'a mut State,
-    stack: Vec<Type>,
+    stack: BlockStack,
+    /// Stacks of nested blocks in current statement
+    nested: Vec<BlockStack>,
+    /// Unmount every nested block
+    unmount: bool,
 }
 
-impl<'a, 'b> RetainedLetExpander<'a> {
-    pub fn expand(state_arg: Ident, depth: usize, state: &'a mut State, block: &mut Block) {
-        let block_state = format_ident!("{}{}", state_arg, depth, span = Span::mixed_site());
-
-        let mut this = Self {
+impl<'a> RetainedLetExpander<'a> {
+    fn new(
+        state_arg: Expr,
+        args: &'a [(Ident, Type)],
+        depth: usize,
+        state: &'a mut State,
+        unmount: bool,
+    ) -> Self {
+        Self {
             state_arg,
-            block_state,
+            args,
+            block_state: format_ident!("__block{}", depth, span = Span::mixed_site()),
+            children: format_ident!("__children{}", depth, span = Span::mixed_site()),
             depth,
             state,
-            stack: Vec::new(),
-        };
-
-        for stmt in &mut block.stmts {
-            this.visit_stmt_mut(stmt);
-
-            *stmt = match stmt {
-                Stmt::Local(ref mut local) => match RetainedLetStmt::try_from_local(local) {
-                    Some(Ok(retained_let)) => this.low(retained_let),
-
-                    Some(Err(err)) => Stmt::Expr(
-                        Expr::Verbatim(err.to_compile_error()),
-                        Some(Default::default()),
-                    ),
-
-                    _ => continue,
-                },
-                _ => continue,
-            };
+            stack: BlockStack {
+                unmount,
+                ..Default::default()
+            },
+            nested: Vec::new(),
+            unmount,
         }
+    }
 
-        if this.stack.is_empty() {
+    pub fn expand(
+        state_arg: Expr,
+        args: &'a [(Ident, Type)],
+        state: &'a mut State,
+        block: &mut Block,
+        unmount: bool,
+    ) {
+        let mut this = Self::new(state_arg, args, 0, state, unmount);
+        this.expand_block(block);
+
+        if this.stack.entries.is_empty() {
             return;
         }
 
-        let index = Index::from(this.state.fields.len());
-        this.state.fields.push(StateField {
-            ty: {
-                let mut state_ty = Type::Tuple(TypeTuple {
-                    paren_token: Default::default(),
-                    elems: Default::default(),
-                });
-
-                for ty in this.stack.into_iter().rev() {
-                    state_ty = parse_quote!(
-                        ::core::option::Option<(#ty , #state_ty)>
-                    );
-                }
-
-                state_ty
-            },
-            init: parse_quote!(::core::option::Option::None),
-        });
+        // Function body is entered on every call
+        this.stack.unmount = false;
 
+        let index = Index::from(this.state.fields.len());
         let state_arg = &this.state_arg;
         let block_state = &this.block_state;
+        let enter = this.stack.enter(
+            quote_spanned!(Span::mixed_site() => #state_arg. #index),
+            None,
+        );
         block.stmts.insert(
             0,
             Stmt::Expr(
                 Expr::Verbatim(quote_spanned! { Span::mixed_site() =>
-                    let #block_state = &mut #state_arg. #index;
+                    let #block_state = #enter;
                 }),
                 Some(Default::default()),
             ),
         );
+
+        this.state.fields.push(StateField {
+            ty: this.stack.ty(),
+            init: parse_quote!(::core::default::Default::default()),
+            kind: FieldKind::Inplace(this.stack),
+        });
+    }
+
+    fn expand_block(&mut self, block: &mut Block) {
+        let mut stmts = Vec::with_capacity(block.stmts.len());
+
+        for mut stmt in block.stmts.drain(..) {
+            self.visit_stmt_mut(&mut stmt);
+            if !self.nested.is_empty() {
+                stmts.push(self.low_children());
+            }
+
+            stmts.push(match stmt {
+                Stmt::Local(ref local) => match RetainedLetStmt::try_from_local(local) {
+                    Some(Ok(retained_let)) => self.low(retained_let),
+
+                    Some(Err(err)) => Stmt::Expr(
+                        Expr::Verbatim(err.to_compile_error()),
+                        Some(Default::default()),
+                    ),
+
+                    None => stmt,
+                },
+                Stmt::Macro(ref stmt_macro) => match EffectStmt::try_from_macro(&stmt_macro.mac) {
+                    Some(Ok(effect)) => self.low_effect(effect),
+
+                    Some(Err(err)) => Stmt::Expr(
+                        Expr::Verbatim(err.to_compile_error()),
+                        Some(Default::default()),
+                    ),
+
+                    None => stmt,
+                },
+                _ => stmt,
+            });
+        }
+
+        block.stmts = stmts;
     }
 
     fn low(&mut self, retaind_let: RetainedLetStmt) -> Stmt {
         match retaind_let {
-            RetainedLetStmt::Inplace(inplace) => inplace.low(&self.block_state, &mut self.stack),
+            RetainedLetStmt::Inplace(mut inplace) => {
+                if let Some(Err(err)) = inplace
+                    .options
+                    .memo
+                    .as_mut()
+                    .map(|deps| deps.resolve(self.args))
+                {
+                    return Stmt::Expr(
+                        Expr::Verbatim(err.to_compile_error()),
+                        Some(Default::default()),
+                    );
+                }
+
+                inplace.low(&self.block_state, &mut self.stack)
+            }
             RetainedLetStmt::Default(default) => default.low(&self.state_arg, self.state),
+            RetainedLetStmt::Keyed(keyed) => keyed.low(&self.state_arg, self.state),
         }
     }
+
+    fn low_effect(&mut self, mut effect: EffectStmt) -> Stmt {
+        if let Err(err) = effect.deps.resolve(self.args) {
+            return Stmt::Expr(
+                Expr::Verbatim(err.to_compile_error()),
+                Some(Default::default()),
+            );
+        }
+
+        effect.low(&self.block_state, &mut self.stack)
+    }
+
+    /// Call counter of the state if any of the stacks are mounted
+    fn calls(&mut self, stacks: &[BlockStack]) -> Option<proc_macro2::TokenStream> {
+        if !stacks.iter().any(BlockStack::mounted) {
+            return None;
+        }
+
+        let state_arg = &self.state_arg;
+        let index = self.state.calls();
+        Some(quote_spanned!(Span::mixed_site() => #state_arg. #index))
+    }
+
+    /// Entry holding stacks of nested blocks in a statement
+    fn low_children(&mut self) -> Stmt {
+        let nested = std::mem::take(&mut self.nested);
+        let children = &self.children;
+
+        let entry = stack::split_entry(&self.block_state, children);
+        let sweeps = nested
+            .iter()
+            .enumerate()
+            .filter_map(|(index, stack)| stack.sweep(child_slot(children, index)))
+            .collect::<Vec<_>>();
+
+        self.stack.entries.push(StackEntry::Children(nested));
+        Stmt::Expr(
+            Expr::Verbatim(quote_spanned!(Span::mixed_site() =>
+                #entry
+                #(#sweeps)*
+            )),
+            Some(Default::default()),
+        )
+    }
 }
 
 impl VisitMut for RetainedLetExpander<'_> {
-    fn visit_block_mut(&mut self, i: &mut syn::Block) {
-        if i.stmts.is_empty() {
+    fn visit_stmt_mut(&mut self, i: &mut Stmt) {
+        if let Stmt::Macro(ref stmt_macro) = *i {
+            if let Some(call) = CallExpr::try_from_macro(&stmt_macro.mac) {
+                *i = Stmt::Expr(lower_call(call), stmt_macro.semi_token);
+            }
+        }
+
+        visit_mut::visit_stmt_mut(self, i);
+    }
+
+    fn visit_expr_mut(&mut self, i: &mut Expr) {
+        if let Expr::Macro(ref expr_macro) = *i {
+            if let Some(call) = CallExpr::try_from_macro(&expr_macro.mac) {
+                *i = lower_call(call);
+            }
+        }
+
+        visit_mut::visit_expr_mut(self, i);
+    }
+
+    fn visit_block_mut(&mut self, i: &mut Block) {
+        let mut child = RetainedLetExpander::new(
+            self.state_arg.clone(),
+            self.args,
+            self.depth + 1,
+            self.state,
+            self.unmount,
+        );
+        child.expand_block(i);
+
+        let RetainedLetExpander {
+            block_state, stack, ..
+        } = child;
+        if stack.entries.is_empty() {
             return;
         }
 
-        RetainedLetExpander::expand(self.state_arg.clone(), self.depth + 1, self.state, i);
+        let calls = self.calls(std::slice::from_ref(&stack));
+        let enter = stack.enter(
+            child_slot(&self.children, self.nested.len()),
+            calls.as_ref(),
+        );
+        i.stmts.insert(
+            0,
+            Stmt::Expr(
+                Expr::Verbatim(quote_spanned! { Span::mixed_site() =>
+                    let #block_state = #enter;
+                }),
+                Some(Default::default()),
+            ),
+        );
+
+        self.nested.push(stack);
+    }
+
+    fn visit_item_mut(&mut self, _: &mut Item) {}
+}
+
+/// Block of `call!` expression, visited like other blocks to store state of the callee
+fn lower_call(call: syn::Result<CallExpr>) -> Expr {
+    match call {
+        Ok(call) => call.low(),
+        Err(err) => Expr::Verbatim(err.to_compile_error()),
     }
 }
 
+/// Macro is `retained::<name>!` or `::retained::<name>!`.
+/// Bare names are not matched, so macros of other crates with same name are left alone.
+fn is_retained_macro(mac: &Macro, name: &str) -> bool {
+    let mut segments = mac.path.segments.iter().map(|segment| &segment.ident);
+    match (segments.next(), segments.next(), segments.next()) {
+        (Some(krate), Some(ident), None) => krate == "retained" && ident == name,
+        _ => false,
+    }
+}
+
+/// Place of nested block stack in children entry
+fn child_slot(children: &Ident, index: usize) -> proc_macro2::TokenStream {
+    stack::nth(children, index)
+}
+
 struct LocalTyVisitor {
     pub ty: Option<Type>,
 }
@@ -196,6 +426,139 @@ impl Visit<'_> for LocalTyVisitor {
     }
 }
 
+/// Finds elided lifetimes in a type stored in the state, which has no lifetime to infer them from
+#[derive(Default)]
+struct ElidedLifetimeVisitor {
+    error: Option<syn::Error>,
+}
+
+impl ElidedLifetimeVisitor {
+    fn check(ty: &Type) -> syn::Result<()> {
+        let mut this = Self::default();
+        this.visit_type(ty);
+
+        match this.error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn elided(&mut self, tokens: impl ToTokens) {
+        let err = syn::Error::new_spanned(
+            tokens,
+            "lifetime in type of retained let cannot be elided, use a lifetime parameter of the function or the state",
+        );
+        match self.error {
+            Some(ref mut error) => error.combine(err),
+            None => self.error = Some(err),
+        }
+    }
+}
+
+impl Visit<'_> for ElidedLifetimeVisitor {
+    fn visit_type_reference(&mut self, i: &TypeReference) {
+        if i.lifetime.is_none() {
+            self.elided(i.and_token);
+        }
+
+        syn::visit::visit_type_reference(self, i);
+    }
+
+    fn visit_lifetime(&mut self, i: &Lifetime) {
+        if i.ident == "_" {
+            self.elided(i);
+        }
+    }
+
+    // Elided lifetimes of function types are higher-ranked
+    fn visit_type_bare_fn(&mut self, _: &TypeBareFn) {}
+
+    fn visit_parenthesized_generic_arguments(&mut self, _: &ParenthesizedGenericArguments) {}
+}
+
+/// Serialization options of retained let
+#[derive(Clone, Default)]
+pub struct SerdeOptions {
+    /// Excluded from serialization
+    pub skip: bool,
+    /// Identifier of serialized field instead of binding name
+    pub id: Option<LitStr>,
+}
+
+impl SerdeOptions {
+    fn peek(lookahead: &Lookahead1) -> bool {
+        lookahead.peek(kw::skip_serde) || lookahead.peek(kw::id)
+    }
+
+    /// Parse `skip_serde` or `id = "..."`
+    fn parse_option(&mut self, input: ParseStream) -> syn::Result<()> {
+        if input.peek(kw::skip_serde) {
+            let skip = input.parse::<kw::skip_serde>()?;
+            if self.skip {
+                return Err(syn::Error::new_spanned(
+                    skip,
+                    "duplicate option `skip_serde`",
+                ));
+            }
+
+            self.skip = true;
+        } else {
+            let id = input.parse::<kw::id>()?;
+            if self.id.is_some() {
+                return Err(syn::Error::new_spanned(id, "duplicate option `id`"));
+            }
+
+            input.parse::<Token![=]>()?;
+            self.id = Some(input.parse()?);
+        }
+
+        Ok(())
+    }
+
+    /// Explicit identifier needs a binding to serialize
+    fn check(&self, pat: &Pat) -> syn::Result<()> {
+        match self.id {
+            Some(ref id) if Binding::find(pat, self).is_none() => Err(syn::Error::new_spanned(
+                id,
+                "`id` can only be given to retained let binding an identifier",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Binding of retained let used by generated methods of the state
+#[derive(Clone)]
+pub struct Binding {
+    pub name: Ident,
+    pub serde: SerdeOptions,
+    /// State of a function called with `call!`, which has no accessors
+    pub callee: bool,
+}
+
+impl Binding {
+    /// Identifier bound by the pattern of retained let
+    fn find(pat: &Pat, serde: &SerdeOptions) -> Option<Self> {
+        match pat {
+            Pat::Ident(pat) => Some(Self {
+                name: pat.ident.clone(),
+                serde: serde.clone(),
+                callee: false,
+            }),
+            Pat::Type(pat) => Self::find(&pat.pat, serde),
+            _ => None,
+        }
+    }
+
+    /// Identifier of serialized field
+    pub fn id(&self) -> String {
+        match self.serde.id {
+            Some(ref id) => id.value(),
+            None => self.name.to_string(),
+        }
+    }
+}
+
 fn extract_init(local: &Local) -> syn::Result<Expr> {
     match local.init {
         Some(LocalInit {
@@ -0,0 +1,411 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
+use syn::{parse_quote, Ident, Index, Type};
+
+use super::Binding;
+
+/// Stack of lazily initialized inplace lets in a block
+#[derive(Default)]
+pub struct BlockStack {
+    pub entries: Vec<StackEntry>,
+    /// Block has separate stack for each iteration
+    pub per_iteration: bool,
+    /// Stack is dropped when the block is not entered during a call
+    pub unmount: bool,
+}
+
+impl BlockStack {
+    /// Type of slot storing the stack
+    pub fn ty(&self) -> Type {
+        let mut ty: Type = parse_quote!(());
+        for entry in self.entries.iter().rev() {
+            let entry_ty = entry.ty();
+            ty = parse_quote!((#entry_ty, #ty));
+        }
+
+        if self.per_iteration {
+            ty = parse_quote!(::retained::__private::Iterations<#ty>);
+        } else if self.unmount {
+            ty = parse_quote!(::retained::__private::Mount<#ty>);
+        }
+
+        ty
+    }
+
+    /// Stack is wrapped to track calls entered the block.
+    /// Stacks for each iteration are already dropped if the block is not entered.
+    pub fn mounted(&self) -> bool {
+        self.unmount && !self.per_iteration
+    }
+
+    /// Expression borrowing the stack from its slot on block entry
+    pub fn enter(&self, slot: impl ToTokens, calls: Option<&TokenStream>) -> TokenStream {
+        match calls {
+            _ if self.per_iteration => quote_spanned!(Span::mixed_site() => #slot .enter()),
+            Some(calls) => quote_spanned!(Span::mixed_site() => #slot .enter(#calls)),
+            None => quote_spanned!(Span::mixed_site() => &mut #slot),
+        }
+    }
+
+    /// Collect accessors of named lets in the stack and its nested stacks
+    pub fn accessors(&self, steps: &mut Vec<Step>, accessors: &mut Vec<Accessor>) {
+        if self.per_iteration {
+            steps.push(Step::Iteration);
+        } else if self.unmount {
+            steps.push(Step::Mount);
+        }
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            steps.push(Step::Entry(index));
+            match entry {
+                StackEntry::Let(ty, Some(binding)) => accessors.push(Accessor {
+                    binding: binding.clone(),
+                    ty: ty.clone(),
+                    steps: [&steps[..], &[Step::Init]].concat(),
+                }),
+
+                StackEntry::Memo(ty, _, Some(binding)) => accessors.push(Accessor {
+                    binding: binding.clone(),
+                    ty: ty.clone(),
+                    steps: [&steps[..], &[Step::Init, Step::Memo]].concat(),
+                }),
+
+                StackEntry::Children(stacks) => {
+                    for (index, stack) in stacks.iter().enumerate() {
+                        steps.push(Step::Child(index));
+                        stack.accessors(steps, accessors);
+                        steps.pop();
+                    }
+                }
+
+                _ => {}
+            }
+            steps.pop();
+        }
+
+        if self.per_iteration || self.unmount {
+            steps.pop();
+        }
+    }
+
+    /// Statement preparing the slot before the block can be entered
+    pub fn sweep(&self, slot: impl ToTokens) -> Option<TokenStream> {
+        if self.per_iteration {
+            Some(quote_spanned!(Span::mixed_site() => #slot .sweep();))
+        } else {
+            None
+        }
+    }
+
+    /// Stack or its nested stacks store an effect
+    pub fn has_effect(&self) -> bool {
+        self.entries.iter().any(|entry| match entry {
+            StackEntry::Effect(_) => true,
+            StackEntry::Children(stacks) => stacks.iter().any(BlockStack::has_effect),
+            _ => false,
+        })
+    }
+
+    /// Stack or its nested stacks are mounted
+    fn has_mounted(&self) -> bool {
+        self.mounted()
+            || self.entries.iter().any(|entry| match entry {
+                StackEntry::Children(stacks) => stacks.iter().any(BlockStack::has_mounted),
+                _ => false,
+            })
+    }
+
+    /// Statements dropping mounted stacks of blocks not entered during previous call,
+    /// run at the beginning of each call after the call counter is incremented.
+    /// Blocks can be skipped by early returns, so the stacks cannot be swept on the statements containing them.
+    pub fn sweep_mounted(&self, slot: impl ToTokens, calls: &TokenStream) -> TokenStream {
+        if !self.has_mounted() {
+            return TokenStream::new();
+        }
+
+        let (sweep, stack) = if self.per_iteration {
+            (None, quote_spanned!(Span::mixed_site() => __stack))
+        } else if self.mounted() {
+            (
+                Some(quote_spanned!(Span::mixed_site() => #slot .sweep(#calls);)),
+                quote_spanned!(Span::mixed_site() => #slot .get_mut()),
+            )
+        } else {
+            (None, slot.to_token_stream())
+        };
+
+        let mut nested = TokenStream::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            if let StackEntry::Children(stacks) = entry {
+                for (child, nested_stack) in stacks.iter().enumerate() {
+                    nested
+                        .extend(nested_stack.sweep_mounted(nth(nth(&stack, index), child), calls));
+                }
+            }
+        }
+
+        if self.per_iteration {
+            quote_spanned!(Span::mixed_site() =>
+                for #stack in #slot .iter_mut() {
+                    #nested
+                }
+            )
+        } else {
+            quote_spanned!(Span::mixed_site() =>
+                #sweep
+                #nested
+            )
+        }
+    }
+}
+
+pub enum StackEntry {
+    /// Inplace let and its binding
+    Let(Type, Option<Binding>),
+    /// Memoized value and stored copies of its dependencies
+    Memo(Type, Box<Type>, Option<Binding>),
+    /// Effect and stored copies of its dependencies
+    Effect(Box<Type>),
+    /// Stacks of nested blocks in a statement
+    Children(Vec<BlockStack>),
+}
+
+impl StackEntry {
+    /// Type of slot storing the entry
+    pub fn ty(&self) -> Type {
+        match self {
+            Self::Let(ty, _) => parse_quote!(::core::option::Option<#ty>),
+
+            Self::Memo(ty, deps, _) => parse_quote!(::core::option::Option<(#ty, #deps)>),
+
+            Self::Effect(deps) => {
+                parse_quote!(::core::option::Option<(::retained::__private::Effect, #deps)>)
+            }
+
+            Self::Children(stacks) => {
+                let mut ty: Type = parse_quote!(());
+                for stack in stacks.iter().rev() {
+                    let stack_ty = stack.ty();
+                    ty = parse_quote!((#stack_ty, #ty));
+                }
+
+                ty
+            }
+        }
+    }
+}
+
+/// Step from a slot to an inplace let
+#[derive(Clone, Copy)]
+pub enum Step {
+    /// Stack of an iteration
+    Iteration,
+    /// Stack of an unmountable block
+    Mount,
+    /// Entry of a stack
+    Entry(usize),
+    /// Initialized value of an entry
+    Init,
+    /// Stack in a children entry
+    Child(usize),
+    /// Value of memoized let
+    Memo,
+}
+
+/// Inplace let reachable from a field of the state
+pub struct Accessor {
+    pub binding: Binding,
+    pub ty: Type,
+    pub steps: Vec<Step>,
+}
+
+impl Accessor {
+    /// Number of iteration index arguments
+    pub fn iterations(&self) -> usize {
+        self.steps
+            .iter()
+            .filter(|step| matches!(step, Step::Iteration))
+            .count()
+    }
+
+    /// Expression evaluating to the place of the let from the slot, using `?` on uninitialized entries.
+    /// Iteration indices are taken from `indices`.
+    pub fn low(&self, slot: impl ToTokens, indices: &[Ident], mutable: bool) -> TokenStream {
+        low_steps(&self.steps, slot, indices, mutable)
+    }
+
+    /// Let is stored once per state and its value does not depend on other entries,
+    /// so it can be restored by placing the value into its entry
+    pub fn restorable(&self) -> bool {
+        !self
+            .steps
+            .iter()
+            .any(|step| matches!(step, Step::Iteration | Step::Memo))
+    }
+
+    /// Place of the `Option` entry of a restorable let
+    pub fn entry(&self, slot: impl ToTokens, mutable: bool) -> TokenStream {
+        debug_assert!(self.restorable());
+        low_steps(&self.steps[..self.steps.len() - 1], slot, &[], mutable)
+    }
+
+    /// Value is memoized with its dependencies
+    pub fn memo(&self) -> bool {
+        matches!(self.steps.last(), Some(Step::Memo))
+    }
+
+    /// Statements running `body` on the place of the `Option` entry of the let in each iteration,
+    /// given indices of the iterations
+    pub fn visit(
+        &self,
+        slot: impl ToTokens,
+        mutable: bool,
+        body: impl Fn(&TokenStream, &[Ident]) -> TokenStream,
+    ) -> TokenStream {
+        let init = self
+            .steps
+            .iter()
+            .position(|step| matches!(step, Step::Init))
+            .unwrap();
+
+        visit_steps(
+            &self.steps[..init],
+            slot.into_token_stream(),
+            mutable,
+            &mut Vec::new(),
+            &body,
+        )
+    }
+
+    /// Expression implementing `Debug`, printing the let or list of it for each iteration.
+    /// Requires `ViaDebug` and `ViaOpaque` in scope.
+    pub fn debug(&self, slot: impl ToTokens) -> TokenStream {
+        debug_steps(&self.steps, slot.into_token_stream(), 0)
+    }
+}
+
+fn low_steps(steps: &[Step], slot: impl ToTokens, indices: &[Ident], mutable: bool) -> TokenStream {
+    let (as_ref, get) = if mutable {
+        (quote!(as_mut), quote!(get_mut))
+    } else {
+        (quote!(as_ref), quote!(get))
+    };
+
+    let mut indices = indices.iter();
+    let mut place = slot.into_token_stream();
+    for step in steps {
+        place = match *step {
+            Step::Iteration => {
+                let index = indices.next().unwrap();
+                quote_spanned!(Span::mixed_site() => #place .#get(#index)?)
+            }
+
+            Step::Mount => quote_spanned!(Span::mixed_site() => #place .#get()),
+
+            Step::Entry(index) | Step::Child(index) => nth(place, index),
+
+            Step::Init => quote_spanned!(Span::mixed_site() => (*#place .#as_ref()?)),
+
+            Step::Memo => quote_spanned!(Span::mixed_site() => #place .0),
+        };
+    }
+
+    place
+}
+
+fn visit_steps(
+    steps: &[Step],
+    slot: TokenStream,
+    mutable: bool,
+    indices: &mut Vec<Ident>,
+    body: &dyn Fn(&TokenStream, &[Ident]) -> TokenStream,
+) -> TokenStream {
+    let split = steps
+        .iter()
+        .position(|step| matches!(step, Step::Iteration))
+        .unwrap_or(steps.len());
+    let place = low_steps(&steps[..split], slot, &[], mutable);
+    if split == steps.len() {
+        return body(&place, indices);
+    }
+
+    let index = format_ident!("__iteration{}", indices.len(), span = Span::mixed_site());
+    let item = format_ident!("__slot{}", indices.len(), span = Span::mixed_site());
+    indices.push(index.clone());
+    let inner = visit_steps(
+        &steps[split + 1..],
+        item.to_token_stream(),
+        mutable,
+        indices,
+        body,
+    );
+    indices.pop();
+
+    let iter = if mutable {
+        quote!(iter_mut)
+    } else {
+        quote!(iter)
+    };
+    quote_spanned!(Span::mixed_site() =>
+        for (#index, #item) in #place .#iter().enumerate() {
+            #inner
+        }
+    )
+}
+
+fn debug_steps(steps: &[Step], slot: TokenStream, depth: usize) -> TokenStream {
+    let split = steps
+        .iter()
+        .position(|step| matches!(step, Step::Iteration))
+        .unwrap_or(steps.len());
+    let place = low_steps(&steps[..split], slot, &[], false);
+
+    let found = if split == steps.len() {
+        quote_spanned!(Span::mixed_site() =>
+            (&::retained::__private::DebugWrap(__found)).retained_fmt(__f)
+        )
+    } else {
+        let item = format_ident!("__slot{}", depth, span = Span::mixed_site());
+        let inner = debug_steps(&steps[split + 1..], item.to_token_stream(), depth + 1);
+        quote_spanned!(Span::mixed_site() =>
+            __f.debug_list().entries(__found.iter().map(|#item| #inner)).finish()
+        )
+    };
+
+    quote_spanned!(Span::mixed_site() =>
+        ::retained::__private::debug_fn(move |__f| {
+            match (|| ::core::option::Option::Some(&#place))() {
+                ::core::option::Option::Some(__found) => #found,
+                ::core::option::Option::None => __f.write_str("<uninit>"),
+            }
+        })
+    )
+}
+
+/// Place of nth element of a cons list
+pub fn nth(list: impl ToTokens, index: usize) -> TokenStream {
+    let rest = std::iter::repeat_n(Index::from(1), index);
+    quote_spanned!(Span::mixed_site() => #list #(. #rest)* .0)
+}
+
+/// Bind next entry of the stack to `binding`
+pub fn split_entry(block_state: &Ident, binding: &Ident) -> TokenStream {
+    quote_spanned!(Span::mixed_site() =>
+        let (#binding, #block_state) = #block_state;
+    )
+}
+
+/// Initialize next entry of the stack if needed and bind its value
+pub fn low_entry(block_state: &Ident, binding: &Ident, init: impl ToTokens) -> TokenStream {
+    let split = split_entry(block_state, binding);
+
+    quote_spanned!(Span::mixed_site() =>
+        #split
+        if #binding.is_none() {
+            * #binding = ::core::option::Option::Some({
+                #init
+            });
+        }
+        let #binding = #binding.as_mut().unwrap();
+    )
+}
@@ -0,0 +1,313 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote_spanned};
+use syn::{parse_quote, GenericParam, Generics, Ident, Index, Type};
+
+use crate::{
+    retained_let::Binding,
+    state::{FieldKind, State},
+};
+
+/// Retained let persisted under its identifier
+struct SerdeField {
+    binding: Binding,
+    id: String,
+    ty: Type,
+    /// Local holding the value during serialization or restoration
+    local: Ident,
+    place: SerdePlace,
+}
+
+enum SerdePlace {
+    /// Field of the state, always present
+    Field(Index),
+    /// `Option` entry of an inplace let, absent if uninitialized
+    Entry {
+        entry: TokenStream,
+        entry_mut: TokenStream,
+    },
+}
+
+impl State {
+    /// Named retained lets which are not skipped
+    fn serde_fields(&self) -> Vec<SerdeField> {
+        let mut fields = Vec::new();
+        let mut push = |binding: &Binding, ty: &Type, place| {
+            fields.push(SerdeField {
+                binding: binding.clone(),
+                id: binding.id(),
+                ty: ty.clone(),
+                local: format_ident!("__field{}", fields.len(), span = Span::mixed_site()),
+                place,
+            })
+        };
+
+        for (index, field) in self.fields.iter().enumerate() {
+            let index = Index::from(index);
+            match field.kind {
+                FieldKind::Default(Some(ref binding))
+                | FieldKind::Keyed {
+                    name: Some(ref binding),
+                    ..
+                } if !binding.serde.skip => push(binding, &field.ty, SerdePlace::Field(index)),
+
+                FieldKind::Inplace(ref stack) => {
+                    let mut accessors = Vec::new();
+                    stack.accessors(&mut Vec::new(), &mut accessors);
+                    for accessor in accessors {
+                        if accessor.binding.serde.skip || !accessor.restorable() {
+                            continue;
+                        }
+
+                        let place = SerdePlace::Entry {
+                            entry: accessor
+                                .entry(quote_spanned!(Span::mixed_site() => self.0. #index), false),
+                            entry_mut: accessor.entry(
+                                quote_spanned!(Span::mixed_site() => __state.0. #index),
+                                true,
+                            ),
+                        };
+                        push(&accessor.binding, &accessor.ty, place);
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        fields
+    }
+
+    /// `Serialize` implementation, `restore` method and `Deserialize` implementation
+    /// if the state has no constructor arguments
+    pub fn serde_impls(&self) -> TokenStream {
+        let name = &self.decl.name;
+        let fields = self.serde_fields();
+        let (impl_gen, ty_gen, where_gen) = self.decl.generics.split_for_impl();
+
+        let mut tokens = TokenStream::new();
+        for (index, field) in fields.iter().enumerate() {
+            if fields[..index].iter().any(|other| other.id == field.id) {
+                let message = format!(
+                    "duplicate serialized field `{}`, give one of retained lets another `id` or skip it with `skip_serde`",
+                    field.id
+                );
+                tokens.extend(
+                    match field.binding.serde.id {
+                        Some(ref id) => syn::Error::new_spanned(id, message),
+                        None => syn::Error::new_spanned(&field.binding.name, message),
+                    }
+                    .to_compile_error(),
+                );
+            }
+        }
+
+        let names = fields.iter().map(|field| &field.id).collect::<Vec<_>>();
+        let locals = fields.iter().map(|field| &field.local).collect::<Vec<_>>();
+        let tys = fields.iter().map(|field| &field.ty).collect::<Vec<_>>();
+        let indices = (0..fields.len()).collect::<Vec<_>>();
+
+        let ser_generics = self.bounded(&tys, quote_spanned!(Span::mixed_site() => Serialize));
+        let ser_where = ser_generics.where_clause.as_ref();
+        let values = fields.iter().map(|field| match field.place {
+            SerdePlace::Field(ref index) => quote_spanned!(Span::mixed_site() =>
+                ::core::option::Option::Some(&self.0. #index)
+            ),
+            SerdePlace::Entry { ref entry, .. } => quote_spanned!(Span::mixed_site() =>
+                #entry .as_ref()
+            ),
+        });
+        tokens.extend(quote_spanned!(Span::mixed_site() =>
+            impl #impl_gen ::retained::__private::serde::Serialize for #name #ty_gen #ser_where {
+                fn serialize<__S: ::retained::__private::serde::Serializer>(
+                    &self,
+                    __serializer: __S,
+                ) -> ::core::result::Result<__S::Ok, __S::Error> {
+                    use ::retained::__private::serde::ser::SerializeStruct as _;
+
+                    #(let #locals = #values;)*
+                    let __len = 0 #(+ usize::from(#locals.is_some()))*;
+                    let mut __struct = __serializer.serialize_struct(::core::stringify!(#name), __len)?;
+                    #(
+                        match #locals {
+                            ::core::option::Option::Some(__value) => __struct.serialize_field(#names, __value)?,
+                            ::core::option::Option::None => __struct.skip_field(#names)?,
+                        }
+                    )*
+                    __struct.end()
+                }
+            }
+        ));
+
+        let de_generics = self.bounded(
+            &tys,
+            quote_spanned!(Span::mixed_site() => Deserialize<'__de>),
+        );
+        let de_where = de_generics
+            .where_clause
+            .as_ref()
+            .map(|clause| &clause.predicates);
+
+        let mut restore_generics = self.decl.generics.clone();
+        restore_generics
+            .params
+            .insert(0, GenericParam::Lifetime(parse_quote!('__a)));
+        let (restore_impl_gen, restore_ty_gen, _) = restore_generics.split_for_impl();
+        let mut visitor_generics = de_generics.clone();
+        visitor_generics
+            .params
+            .insert(0, GenericParam::Lifetime(parse_quote!('__a)));
+        visitor_generics
+            .params
+            .insert(0, GenericParam::Lifetime(parse_quote!('__de)));
+        let (visitor_impl_gen, _, visitor_where) = visitor_generics.split_for_impl();
+
+        // Fields missing in the input keep their values
+        let assigns = fields.iter().map(|field| {
+            let local = &field.local;
+            match field.place {
+                SerdePlace::Field(ref index) => quote_spanned!(Span::mixed_site() =>
+                    if let ::core::option::Option::Some(__value) = #local {
+                        __state.0. #index = __value;
+                    }
+                ),
+                SerdePlace::Entry { ref entry_mut, .. } => quote_spanned!(Span::mixed_site() =>
+                    if let ::core::option::Option::Some(__value) = #local {
+                        #entry_mut = ::core::option::Option::Some(__value);
+                    }
+                ),
+            }
+        });
+
+        tokens.extend(quote_spanned!(Span::mixed_site() =>
+            const __FIELDS: &[&str] = &[#(#names),*];
+
+            /// Index of the field, `None` if unknown
+            struct __Field(::core::option::Option<usize>);
+
+            impl<'__de> ::retained::__private::serde::Deserialize<'__de> for __Field {
+                fn deserialize<__D: ::retained::__private::serde::Deserializer<'__de>>(
+                    __deserializer: __D,
+                ) -> ::core::result::Result<Self, __D::Error> {
+                    struct __FieldVisitor;
+
+                    impl ::retained::__private::serde::de::Visitor<'_> for __FieldVisitor {
+                        type Value = __Field;
+
+                        fn expecting(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                            f.write_str("field identifier")
+                        }
+
+                        fn visit_str<__E: ::retained::__private::serde::de::Error>(
+                            self,
+                            __value: &str,
+                        ) -> ::core::result::Result<__Field, __E> {
+                            match __value {
+                                #(#names => ::core::result::Result::Ok(__Field(::core::option::Option::Some(#indices))),)*
+                                _ => ::core::result::Result::Ok(__Field(::core::option::Option::None)),
+                            }
+                        }
+                    }
+
+                    __deserializer.deserialize_identifier(__FieldVisitor)
+                }
+            }
+
+            struct __Restore #restore_impl_gen (&'__a mut #name #ty_gen) #where_gen;
+
+            impl #visitor_impl_gen ::retained::__private::serde::de::Visitor<'__de>
+                for __Restore #restore_ty_gen #visitor_where
+            {
+                type Value = ();
+
+                fn expecting(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                    f.write_str(::core::concat!("struct ", ::core::stringify!(#name)))
+                }
+
+                fn visit_map<__A: ::retained::__private::serde::de::MapAccess<'__de>>(
+                    self,
+                    mut __map: __A,
+                ) -> ::core::result::Result<(), __A::Error> {
+                    use ::retained::__private::serde::de::Error as _;
+
+                    #(let mut #locals: ::core::option::Option<#tys> = ::core::option::Option::None;)*
+                    while let ::core::option::Option::Some(__key) = __map.next_key::<__Field>()? {
+                        match __key.0 {
+                            #(
+                                ::core::option::Option::Some(#indices) => {
+                                    if #locals.is_some() {
+                                        return ::core::result::Result::Err(__A::Error::duplicate_field(#names));
+                                    }
+                                    #locals = ::core::option::Option::Some(__map.next_value()?);
+                                }
+                            )*
+                            _ => {
+                                __map.next_value::<::retained::__private::serde::de::IgnoredAny>()?;
+                            }
+                        }
+                    }
+
+                    let __state = self.0;
+                    #(#assigns)*
+                    ::core::result::Result::Ok(())
+                }
+            }
+
+            impl #impl_gen #name #ty_gen #where_gen {
+                /// Overwrite retained lets with serialized ones.
+                ///
+                /// Unknown fields are ignored, and retained lets missing in the input keep their values.
+                /// State is left untouched on error.
+                pub fn restore<'__de, __D: ::retained::__private::serde::Deserializer<'__de>>(
+                    &mut self,
+                    __deserializer: __D,
+                ) -> ::core::result::Result<(), __D::Error>
+                where
+                    #de_where
+                {
+                    __deserializer.deserialize_struct(
+                        ::core::stringify!(#name),
+                        __FIELDS,
+                        __Restore(self),
+                    )
+                }
+            }
+        ));
+
+        if self.decl.constructor.is_empty() {
+            let mut impl_generics = de_generics.clone();
+            impl_generics
+                .params
+                .insert(0, GenericParam::Lifetime(parse_quote!('__de)));
+            let (de_impl_gen, _, de_where) = impl_generics.split_for_impl();
+
+            tokens.extend(quote_spanned!(Span::mixed_site() =>
+                impl #de_impl_gen ::retained::__private::serde::Deserialize<'__de> for #name #ty_gen #de_where {
+                    fn deserialize<__D: ::retained::__private::serde::Deserializer<'__de>>(
+                        __deserializer: __D,
+                    ) -> ::core::result::Result<Self, __D::Error> {
+                        let mut __state = Self::new();
+                        __state.restore(__deserializer)?;
+                        ::core::result::Result::Ok(__state)
+                    }
+                }
+            ));
+        }
+
+        tokens
+    }
+
+    /// Generics of the state with serde trait bound on each type of serialized field if the state is generic
+    fn bounded(&self, tys: &[&Type], bound: TokenStream) -> Generics {
+        let mut generics = self.decl.generics.clone();
+        if generics.type_params().next().is_some() {
+            let clause = generics.make_where_clause();
+            for ty in tys {
+                clause
+                    .predicates
+                    .push(parse_quote!(#ty: ::retained::__private::serde::#bound));
+            }
+        }
+
+        generics
+    }
+}
@@ -1,11 +1,18 @@
 use proc_macro2::Span;
-use quote::{quote_spanned, ToTokens};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
 use syn::{
     parenthesized,
     parse::{Parse, ParseStream},
+    parse_quote, parse_quote_spanned,
     punctuated::Punctuated,
     token::{Comma, Paren},
-    Expr, Generics, Ident, PatType, Type, Visibility,
+    Expr, Generics, Ident, Index, Lifetime, PatType, Path, Stmt, Token, Type, Visibility,
+    WhereClause, WherePredicate,
+};
+
+use crate::{
+    attr::RetainedAttr,
+    retained_let::{Binding, BlockStack},
 };
 
 #[derive(Clone)]
@@ -26,7 +33,12 @@ impl Parse for StateDecl {
         } else {
             Punctuated::new()
         };
-        generics.where_clause = input.parse()?;
+        if input.peek(Token![where]) {
+            generics.where_clause = Some(WhereClause {
+                where_token: input.parse()?,
+                predicates: parse_predicates(input)?,
+            });
+        }
 
         Ok(Self {
             name,
@@ -36,23 +48,91 @@ impl Parse for StateDecl {
     }
 }
 
+impl StateDecl {
+    /// Declared only by its name
+    pub fn is_bare(&self) -> bool {
+        self.generics.params.is_empty()
+            && self.generics.where_clause.is_none()
+            && self.constructor.is_empty()
+    }
+
+    /// Declares same generics and constructor
+    pub fn same(&self, other: &Self) -> bool {
+        fn tokens(decl: &StateDecl) -> String {
+            let StateDecl {
+                generics,
+                constructor,
+                ..
+            } = decl;
+            let where_clause = &generics.where_clause;
+            quote!(#generics #where_clause (#constructor)).to_string()
+        }
+
+        tokens(self) == tokens(other)
+    }
+}
+
+/// Parse where predicates until options of the attribute
+fn parse_predicates(input: ParseStream) -> syn::Result<Punctuated<WherePredicate, Comma>> {
+    let mut predicates = Punctuated::new();
+
+    while !input.is_empty() && !peek_option(input) {
+        predicates.push_value(input.parse()?);
+        if !input.peek(Comma) {
+            break;
+        }
+
+        let fork = input.fork();
+        _ = fork.parse::<Comma>()?;
+        if fork.is_empty() || peek_option(&fork) {
+            break;
+        }
+        predicates.push_punct(input.parse()?);
+    }
+
+    Ok(predicates)
+}
+
+/// Options are bare identifier or followed by `=` or parenthesized arguments
+fn peek_option(input: ParseStream) -> bool {
+    input.peek(Ident) && !input.peek2(Token![:]) && !input.peek2(Token![<])
+}
+
 pub struct StateField {
     pub ty: Type,
     pub init: Expr,
+    pub kind: FieldKind,
 }
 
-pub struct StateProvided {
-    pub name: Ident,
-    pub ty: Type,
+pub enum FieldKind {
+    /// Initialized with state
+    Default(Option<Binding>),
+    /// Stack of inplace lets in a block
+    Inplace(BlockStack),
+    /// Keyed slots of a keyed let
+    Keyed {
+        name: Option<Binding>,
+        key: Box<Type>,
+        value: Box<Type>,
+    },
+    /// Counter of calls for unmounting blocks
+    Calls,
 }
 
-impl ToTokens for StateProvided {
-    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
-        let Self { name, ty } = self;
+impl FieldKind {
+    /// Field is initialized lazily on access
+    pub fn lazy(&self) -> bool {
+        matches!(self, Self::Inplace(_) | Self::Keyed { .. })
+    }
 
-        *tokens = quote_spanned!(Span::mixed_site() =>
-            #name : #ty
-        );
+    /// Field needs to be swept at the beginning of each call
+    pub fn sweep(&self) -> bool {
+        match self {
+            Self::Default(_) => false,
+            Self::Inplace(stack) => stack.per_iteration,
+            Self::Keyed { .. } => true,
+            Self::Calls => false,
+        }
     }
 }
 
@@ -60,6 +140,339 @@ pub struct State {
     pub vis: Visibility,
     pub decl: StateDecl,
     pub fields: Vec<StateField>,
+    /// Generate accessors of named retained lets
+    pub accessors: bool,
+    /// Print named retained lets in `Debug` output
+    pub debug: bool,
+    /// Derives applied to state structs
+    pub derives: Punctuated<Path, Comma>,
+    /// Implement serde traits for the state
+    pub serde: bool,
+    /// Index of the first field of the function being expanded, as functions can share a state
+    pub owned: usize,
+}
+
+impl State {
+    pub fn new(vis: &Visibility, attr: &RetainedAttr) -> Self {
+        Self {
+            vis: vis.clone(),
+            decl: attr.decl.clone(),
+            fields: Vec::new(),
+            accessors: attr.accessors,
+            debug: attr.debug,
+            derives: attr.derives.clone(),
+            serde: attr.serde,
+            owned: 0,
+        }
+    }
+
+    /// Apply options given by another function sharing the state
+    pub fn merge(&mut self, attr: &RetainedAttr) {
+        self.accessors |= attr.accessors;
+        self.debug |= attr.debug;
+        self.serde |= attr.serde;
+        for derive in &attr.derives {
+            let name = derive.to_token_stream().to_string();
+            if !self
+                .derives
+                .iter()
+                .any(|other| other.to_token_stream().to_string() == name)
+            {
+                self.derives.push(derive.clone());
+            }
+        }
+    }
+
+    /// Index of call counter field, added on first use
+    pub fn calls(&mut self) -> Index {
+        let index = match self.fields[self.owned..]
+            .iter()
+            .position(|field| matches!(field.kind, FieldKind::Calls))
+        {
+            Some(index) => self.owned + index,
+            None => {
+                self.fields.push(StateField {
+                    ty: parse_quote!(u32),
+                    init: parse_quote!(0),
+                    kind: FieldKind::Calls,
+                });
+
+                self.fields.len() - 1
+            }
+        };
+
+        Index::from(index)
+    }
+
+    /// Getters and `_mut` methods of named retained lets
+    fn accessors(&self) -> proc_macro2::TokenStream {
+        let mut methods = Vec::<(Ident, proc_macro2::TokenStream)>::new();
+
+        for (index, field) in self.fields.iter().enumerate() {
+            let index = Index::from(index);
+            match field.kind {
+                FieldKind::Default(Some(Binding { ref name, .. })) => {
+                    let name_mut = format_ident!("{}_mut", name);
+                    let ty = &field.ty;
+                    let doc = format!("Get retained `{name}`");
+                    let doc_mut = format!("Get retained `{name}` mutably");
+
+                    methods.push((
+                        name.clone(),
+                        quote_spanned!(Span::mixed_site() =>
+                            #[doc = #doc]
+                            pub fn #name(&self) -> &#ty {
+                                &self.0. #index
+                            }
+
+                            #[doc = #doc_mut]
+                            pub fn #name_mut(&mut self) -> &mut #ty {
+                                &mut self.0. #index
+                            }
+                        ),
+                    ));
+                }
+
+                FieldKind::Keyed {
+                    name: Some(Binding { ref name, .. }),
+                    ref key,
+                    ref value,
+                } => {
+                    let name_mut = format_ident!("{}_mut", name);
+                    let doc = format!("Get retained `{name}` of the key if it is stored");
+                    let doc_mut =
+                        format!("Get retained `{name}` of the key mutably if it is stored");
+
+                    methods.push((name.clone(), quote_spanned!(Span::mixed_site() =>
+                        #[doc = #doc]
+                        pub fn #name(&self, key: &#key) -> ::core::option::Option<&#value> {
+                            self.0. #index .get(key)
+                        }
+
+                        #[doc = #doc_mut]
+                        pub fn #name_mut(&mut self, key: &#key) -> ::core::option::Option<&mut #value> {
+                            self.0. #index .get_mut(key)
+                        }
+                    )));
+                }
+
+                FieldKind::Inplace(ref stack) => {
+                    let mut accessors = Vec::new();
+                    stack.accessors(&mut Vec::new(), &mut accessors);
+                    for accessor in accessors {
+                        if accessor.binding.callee {
+                            continue;
+                        }
+
+                        let name = &accessor.binding.name;
+                        let name_mut = format_ident!("{}_mut", name);
+                        let ty = &accessor.ty;
+                        let indices = (0..accessor.iterations())
+                            .map(|index| {
+                                format_ident!("iteration{}", index, span = Span::mixed_site())
+                            })
+                            .collect::<Vec<_>>();
+                        let place = accessor.low(
+                            quote_spanned!(Span::mixed_site() => self.0. #index),
+                            &indices,
+                            false,
+                        );
+                        let place_mut = accessor.low(
+                            quote_spanned!(Span::mixed_site() => self.0. #index),
+                            &indices,
+                            true,
+                        );
+                        let doc = format!("Get retained `{name}` if it is initialized");
+                        let doc_mut = format!("Get retained `{name}` mutably if it is initialized");
+
+                        methods.push((name.clone(), quote_spanned!(Span::mixed_site() =>
+                            #[doc = #doc]
+                            pub fn #name(&self, #(#indices: usize),*) -> ::core::option::Option<&#ty> {
+                                ::core::option::Option::Some(&#place)
+                            }
+
+                            #[doc = #doc_mut]
+                            pub fn #name_mut(&mut self, #(#indices: usize),*) -> ::core::option::Option<&mut #ty> {
+                                ::core::option::Option::Some(&mut #place_mut)
+                            }
+                        )));
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        // Inherent methods generated on every state
+        let mut reserved = vec!["new", "reset", "reset_inplace"];
+        if self.serde {
+            reserved.push("restore");
+        }
+
+        // Each retained let gets a getter and a `_mut` getter
+        let generated = |name: &Ident| [name.to_string(), format!("{name}_mut")];
+
+        let mut tokens = proc_macro2::TokenStream::new();
+        for (index, (name, method)) in methods.iter().enumerate() {
+            let names = generated(name);
+            let earlier = methods[..index]
+                .iter()
+                .flat_map(|(other, _)| generated(other))
+                .collect::<Vec<_>>();
+
+            let message = if let Some(method) = names
+                .iter()
+                .find(|method| reserved.contains(&method.as_str()))
+            {
+                format!("accessor `{method}` collides with `{method}` method of the state, rename the retained let")
+            } else if let Some(method) = names.iter().find(|method| earlier.contains(method)) {
+                format!("duplicate accessor `{method}`, rename one of retained lets")
+            } else {
+                tokens.extend(method.clone());
+                continue;
+            };
+
+            tokens.extend(syn::Error::new_spanned(name, message).to_compile_error());
+        }
+
+        tokens
+    }
+
+    /// Derives of the state and errors of ones which cannot be implemented.
+    /// Effects cannot be cloned or compared, so standard derives requiring it on every field are rejected.
+    fn checked_derives(&self) -> (Vec<&Path>, proc_macro2::TokenStream) {
+        const EFFECT_DERIVES: &[&str] = &[
+            "Clone",
+            "Copy",
+            "PartialEq",
+            "Eq",
+            "PartialOrd",
+            "Ord",
+            "Hash",
+        ];
+
+        let has_effect = self.fields.iter().any(|field| match field.kind {
+            FieldKind::Inplace(ref stack) => stack.has_effect(),
+            _ => false,
+        });
+
+        let mut derives = Vec::new();
+        let mut errors = proc_macro2::TokenStream::new();
+        for derive in &self.derives {
+            let name = derive.segments.last().map(|segment| &segment.ident);
+            match name {
+                Some(name) if has_effect && EFFECT_DERIVES.iter().any(|effect| name == effect) => {
+                    errors.extend(
+                        syn::Error::new_spanned(
+                            derive,
+                            format!("`{name}` cannot be derived for a state storing `effect!`"),
+                        )
+                        .to_compile_error(),
+                    );
+                }
+                _ => derives.push(derive),
+            }
+        }
+
+        (derives, errors)
+    }
+
+    /// Body of `Debug::fmt` printing named retained lets
+    fn debug_fields(&self) -> proc_macro2::TokenStream {
+        let name = &self.decl.name;
+        let mut fields = Vec::new();
+
+        for (index, field) in self.fields.iter().enumerate() {
+            let index = Index::from(index);
+            match field.kind {
+                FieldKind::Default(Some(Binding { ref name, .. })) => fields.push((
+                    name.to_string(),
+                    debug_value(quote_spanned!(Span::mixed_site() => &self.0. #index)),
+                )),
+
+                FieldKind::Keyed {
+                    name: Some(Binding { ref name, .. }),
+                    ..
+                } => {
+                    let key = debug_value(quote_spanned!(Span::mixed_site() => __key));
+                    let value = debug_value(quote_spanned!(Span::mixed_site() => __value));
+                    fields.push((
+                        name.to_string(),
+                        quote_spanned!(Span::mixed_site() =>
+                            ::retained::__private::debug_fn(move |__f| {
+                                __f.debug_map()
+                                    .entries(self.0. #index .iter().map(|(__key, __value)| (#key, #value)))
+                                    .finish()
+                            })
+                        ),
+                    ));
+                }
+
+                FieldKind::Inplace(ref stack) => {
+                    let mut accessors = Vec::new();
+                    stack.accessors(&mut Vec::new(), &mut accessors);
+                    fields.extend(accessors.into_iter().map(|accessor| {
+                        (
+                            accessor.binding.name.to_string(),
+                            accessor.debug(quote_spanned!(Span::mixed_site() => self.0. #index)),
+                        )
+                    }));
+                }
+
+                _ => {}
+            }
+        }
+
+        let field_names = fields.iter().map(|(name, _)| name);
+        let field_values = fields.iter().map(|(_, value)| value);
+        quote_spanned!(Span::mixed_site() =>
+            #[allow(unused_imports)]
+            use ::retained::__private::{ViaDebug as _, ViaOpaque as _};
+
+            f.debug_struct(::core::stringify!(#name))
+                #(.field(#field_names, &#field_values))*
+                .finish()
+        )
+    }
+
+    /// Statements running at the beginning of each call of the function being expanded
+    pub fn prologue(&self, state_arg: &Expr) -> Vec<Stmt> {
+        let fields = self.fields.iter().enumerate().skip(self.owned);
+        let calls = fields
+            .clone()
+            .find(|(_, field)| matches!(field.kind, FieldKind::Calls))
+            .map(|(index, _)| {
+                let index = Index::from(index);
+                quote_spanned!(Span::mixed_site() => #state_arg . #index)
+            });
+
+        // Call counter is incremented before mounted stacks are swept with it
+        let mut stmts: Vec<Stmt> = Vec::new();
+        if let Some(ref calls) = calls {
+            stmts.push(parse_quote_spanned!(Span::mixed_site() =>
+                #calls = #calls .wrapping_add(1);
+            ));
+        }
+
+        // Mounted stacks are swept before iterations, which only visit iterations of the previous call
+        for (index, field) in fields {
+            let index = Index::from(index);
+            if let (FieldKind::Inplace(ref stack), Some(ref calls)) = (&field.kind, &calls) {
+                let sweeps = stack.sweep_mounted(quote!(#state_arg . #index), calls);
+                if !sweeps.is_empty() {
+                    stmts.push(Stmt::Expr(Expr::Verbatim(sweeps), Some(Default::default())));
+                }
+            }
+
+            if field.kind.sweep() {
+                stmts.push(parse_quote_spanned!(Span::mixed_site() =>
+                    #state_arg . #index .sweep();
+                ));
+            }
+        }
+
+        stmts
+    }
 }
 
 impl ToTokens for State {
@@ -73,60 +486,172 @@ impl ToTokens for State {
                     constructor,
                 },
             fields,
+            accessors,
+            debug,
+            serde,
+            ..
         } = self;
 
+        let (derives, derive_errors) = self.checked_derives();
+        let derive = if derives.is_empty() {
+            None
+        } else {
+            Some(quote_spanned!(Span::mixed_site() => #[derive(#(#derives),*)]))
+        };
+
         let (impl_gen, ty_gen, where_gen) = generics.split_for_impl();
 
-        let inner_name = quote::format_ident!("__{}", name, span = Span::mixed_site());
+        let accessors = if *accessors {
+            self.accessors()
+        } else {
+            proc_macro2::TokenStream::new()
+        };
+
+        let reflect = self.reflect_impl();
+
+        let serde = if *serde {
+            self.serde_impls()
+        } else {
+            proc_macro2::TokenStream::new()
+        };
+
+        let debug = if *debug {
+            self.debug_fields()
+        } else {
+            quote_spanned!(Span::mixed_site() =>
+                f.debug_struct(::core::stringify!(#name)).finish_non_exhaustive()
+            )
+        };
+
+        let inner_name = format_ident!("__{}", name, span = Span::mixed_site());
+
+        let mut field_tys = fields
+            .iter()
+            .map(|field| field.ty.to_token_stream())
+            .collect::<Vec<_>>();
+        let mut field_inits = fields
+            .iter()
+            .map(|field| field.init.to_token_stream())
+            .collect::<Vec<_>>();
 
-        let field_ty_iter = fields.iter().map(|field| &field.ty);
-        let field_init_iter = fields.iter().map(|field| &field.init);
+        // Generic parameters may not be used by any retained let
+        let lifetimes = generics
+            .lifetimes()
+            .map(|param| &param.lifetime)
+            .collect::<Vec<_>>();
+        let types = generics
+            .type_params()
+            .map(|param| &param.ident)
+            .collect::<Vec<_>>();
+        if !lifetimes.is_empty() || !types.is_empty() {
+            field_tys.push(quote_spanned!(Span::mixed_site() =>
+                ::core::marker::PhantomData<fn() -> (#(&#lifetimes (),)* #(#types,)*)>
+            ));
+            field_inits.push(quote_spanned!(Span::mixed_site() => ::core::marker::PhantomData));
+        }
+        let field_inits = quote_spanned!(Span::mixed_site() => #(#field_inits),*);
 
-        *tokens = quote_spanned!(Span::mixed_site() =>
-            struct #inner_name #ty_gen (#(#field_ty_iter),*) #where_gen;
+        let inplace_iter = fields
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| field.kind.lazy())
+            .map(|(index, field)| {
+                let index = Index::from(index);
+                let init = &field.init;
+                quote_spanned!(Span::mixed_site() => self.0. #index = #init;)
+            });
 
+        tokens.extend(quote_spanned!(Span::mixed_site() =>
+            #derive_errors
+
+            #derive
+            struct #inner_name #generics (#(#field_tys),*) #where_gen;
+
+            #derive
             #[repr(transparent)]
             #[non_exhaustive]
-            #vis struct #name #ty_gen (
+            #vis struct #name #generics (
                 #inner_name #ty_gen,
             ) #where_gen;
 
             const _: () = {
                 impl #impl_gen ::core::fmt::Debug for #name #ty_gen #where_gen {
                     fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
-                        f.debug_struct(::core::stringify!(#name)).finish_non_exhaustive()
+                        #debug
                     }
                 }
 
+                impl #impl_gen ::retained::__private::Callee for #name #ty_gen #where_gen {
+                    type State = Self;
+                }
+
                 impl #impl_gen #name #ty_gen #where_gen {
                     pub fn new(#constructor) -> Self {
-                        Self(#inner_name (#(#field_init_iter),*))
+                        Self(#inner_name (#field_inits))
+                    }
+
+                    /// Reset whole state as if it is newly created.
+                    pub fn reset(&mut self, #constructor) {
+                        self.0 = #inner_name (#field_inits);
                     }
+
+                    /// Drop lazily initialized variables, so they are initialized again on next access.
+                    pub fn reset_inplace(&mut self) {
+                        #(#inplace_iter)*
+                    }
+
+                    #accessors
                 }
+
+                #reflect
+
+                #serde
             };
-        );
+        ));
     }
 }
 
+/// Expression implementing `Debug` for the value, or opaque marker if it does not implement
+fn debug_value(value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote_spanned!(Span::mixed_site() =>
+        ::retained::__private::debug_fn(move |__f| {
+            (&::retained::__private::DebugWrap(#value)).retained_fmt(__f)
+        })
+    )
+}
+
 pub struct StateArg<'a> {
     pub name: Ident,
+    /// Bind whole state instead of inner struct
+    pub named: bool,
     pub decl: &'a StateDecl,
+    /// Lifetime of the borrow, `'state` if the signature refers to it
+    pub lifetime: Option<Lifetime>,
 }
 
 impl ToTokens for StateArg<'_> {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         let StateArg {
             name,
+            named,
             decl:
                 StateDecl {
                     name: state_ty,
                     generics,
                     ..
                 },
+            lifetime,
         } = self;
+        let (_, ty_gen, _) = generics.split_for_impl();
 
-        *tokens = quote_spanned! { Span::mixed_site() =>
-            #state_ty (#name) : &mut #state_ty #generics
-        };
+        if *named {
+            tokens.extend(quote_spanned! { Span::mixed_site() =>
+                #name : &#lifetime mut #state_ty #ty_gen
+            });
+        } else {
+            tokens.extend(quote_spanned! { Span::mixed_site() =>
+                #state_ty (#name) : &#lifetime mut #state_ty #ty_gen
+            });
+        }
     }
 }